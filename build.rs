@@ -0,0 +1,58 @@
+//! Defines a `cfg` for each group of drivers that share code, so that the
+//! code is gated on the group rather than on a list of driver features that
+//! every new driver would have to be added to.
+
+use std::env;
+
+/// Each group and the features of its drivers.
+const GROUPS: &[(&str, &[&str])] = &[
+    // Every driver.
+    (
+        "driver",
+        &[
+            "clickhouse",
+            "exec",
+            "file",
+            "http",
+            "mssql",
+            "mysql",
+            "postgres",
+            "redis",
+            "sqlite",
+        ],
+    ),
+    // Drivers that connect over the network, with TLS and a password.
+    (
+        "network",
+        &["clickhouse", "http", "mssql", "mysql", "postgres", "redis"],
+    ),
+    // Network drivers whose dsn may be a URI with settings in its query.
+    ("uri", &["clickhouse", "http", "mysql", "postgres", "redis"]),
+    // Network drivers that use `native_tls` directly.
+    ("native_tls", &["clickhouse", "http", "postgres"]),
+    // Drivers with settings that may turn out unusable when connecting.
+    (
+        "settings",
+        &[
+            "clickhouse",
+            "file",
+            "http",
+            "mssql",
+            "mysql",
+            "postgres",
+            "redis",
+        ],
+    ),
+];
+
+fn main() {
+    for (group, features) in GROUPS {
+        println!("cargo::rustc-check-cfg=cfg({})", group);
+        let enabled = features.iter().any(|feature| {
+            env::var_os(format!("CARGO_FEATURE_{}", feature.to_uppercase())).is_some()
+        });
+        if enabled {
+            println!("cargo::rustc-cfg={}", group);
+        }
+    }
+}
@@ -0,0 +1,207 @@
+//! Backing off from databases that cannot be connected to. After a failed
+//! attempt, connecting is not tried again for a second, after the next for
+//! two, then four and so on up to five minutes, less a random part of up to
+//! half so that exporters which lost a database together do not all retry
+//! together. After `failure_threshold` failures in a row the breaker opens:
+//! the database's metrics are skipped but for one probe whenever the wait is
+//! over, until a probe connects. Whether it is open is exported as
+//! `jikji_database_breaker_open`. For drivers that only reach the database
+//! with their queries, such as `clickhouse` and `http`, a query that gets no
+//! response counts as a failed attempt and one that succeeds as a connection.
+
+use crate::config::Database;
+use crate::driver::Error;
+use lazy_static::lazy_static;
+use log::{info, warn};
+use prometheus::core::Collector;
+use prometheus::{Gauge, GaugeVec, Opts};
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+/// How many connection attempts may fail in a row without
+/// `failure_threshold` before the breaker opens.
+pub const FAILURE_THRESHOLD: u32 = 5;
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(5 * 60);
+
+lazy_static! {
+    static ref OPEN: GaugeVec = GaugeVec::new(
+        Opts::new(
+            "jikji_database_breaker_open",
+            "Whether a database's metrics are skipped because reaching it keeps failing.",
+        ),
+        &["database"],
+    )
+    .expect("the gauge's name and label are valid");
+    /// How many breakers set each series of `OPEN`, so that it is removed
+    /// with the last of them.
+    static ref SERIES: Mutex<HashMap<String, usize>> = Mutex::default();
+}
+
+/// The gauge of whether breakers are open, for `telemetry` to register.
+pub fn collectors() -> Vec<Box<dyn Collector>> {
+    vec![Box::new(OPEN.clone())]
+}
+
+pub struct Breaker {
+    database: String,
+    threshold: u32,
+    gauge: Gauge,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    /// The connection attempts that failed in a row.
+    failures: u32,
+    /// When connecting may be tried again, after a failure.
+    retry: Option<Instant>,
+}
+
+impl Breaker {
+    pub fn new(database: &Database) -> Breaker {
+        let label = database.describe();
+        *lock(&SERIES).entry(label.clone()).or_default() += 1;
+        let gauge = OPEN.with_label_values(&[&label]);
+        gauge.set(0.0);
+        Breaker {
+            database: label,
+            threshold: database.failure_threshold.unwrap_or(FAILURE_THRESHOLD),
+            gauge,
+            state: Mutex::default(),
+        }
+    }
+
+    /// Whether connecting may be tried now. Trying moves the next attempt
+    /// back as if this one had failed already, so that while backing off only
+    /// one attempt is made at a time.
+    pub fn admit(&self) -> Result<(), Error> {
+        let mut state = lock(&self.state);
+        let now = Instant::now();
+        match state.retry {
+            Some(retry) if retry > now => Err(Error::BackingOff {
+                failures: state.failures,
+                retry_in: retry - now,
+            }),
+            Some(_) => {
+                state.retry = Some(now + backoff(state.failures.saturating_add(1)));
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Counts a connection attempt, and returns whether its failure opened
+    /// the breaker.
+    pub fn record(&self, reached: bool) -> bool {
+        let mut state = lock(&self.state);
+        let was_open = self.is_open(&state);
+        if reached {
+            if was_open {
+                info!("{} is reachable again, closing its breaker", self.database);
+            }
+            *state = State::default();
+        } else {
+            state.failures = state.failures.saturating_add(1);
+            state.retry = Some(Instant::now() + backoff(state.failures));
+        }
+
+        let open = self.is_open(&state);
+        if open && !was_open {
+            warn!(
+                "reaching {} failed {} times in a row, skipping its metrics until it is reachable",
+                self.database, state.failures
+            );
+        }
+        self.gauge.set(if open { 1.0 } else { 0.0 });
+        open && !was_open
+    }
+
+    fn is_open(&self, state: &State) -> bool {
+        state.failures >= self.threshold
+    }
+}
+
+impl Drop for Breaker {
+    fn drop(&mut self) {
+        let mut series = lock(&SERIES);
+        if let Some(count) = series.get_mut(&self.database) {
+            *count -= 1;
+            if *count == 0 {
+                series.remove(&self.database);
+                let _ = OPEN.remove_label_values(&[&self.database]);
+            }
+        }
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// How long to wait after `failures` failed attempts in a row.
+fn backoff(failures: u32) -> Duration {
+    let doublings = failures.saturating_sub(1).min(16);
+    let full = MIN_BACKOFF.saturating_mul(1 << doublings).min(MAX_BACKOFF);
+    full.mul_f64(1.0 - fastrand::f64() / 2.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn breaker(name: &str, threshold: u32) -> Breaker {
+        Breaker::new(
+            &toml::from_str(&format!(
+                "driver = \"fake\"\npath = \"{}\"\nfailure_threshold = {}\nmetrics = []",
+                name, threshold
+            ))
+            .unwrap(),
+        )
+    }
+
+    #[test]
+    fn backs_off_exponentially_with_jitter() {
+        for (failures, full) in [(1, 1), (2, 2), (4, 8), (9, 256), (10, 300), (40, 300)] {
+            let full = Duration::from_secs(full);
+            for _ in 0..20 {
+                let wait = backoff(failures);
+                assert!(
+                    wait > full / 2 && wait <= full,
+                    "{:?} for {}",
+                    wait,
+                    failures
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn opens_after_failures_until_a_probe_connects() {
+        let breaker = breaker("orders-replica", 2);
+        let gauge = || OPEN.with_label_values(&["fake:orders-replica"]).get();
+        assert!(breaker.admit().is_ok());
+        assert!(!breaker.record(false));
+        assert!(matches!(
+            breaker.admit(),
+            Err(Error::BackingOff { failures: 1, .. })
+        ));
+
+        lock(&breaker.state).retry = Some(Instant::now());
+        assert!(breaker.admit().is_ok());
+        assert!(breaker.record(false));
+        assert_eq!(gauge(), 1.0);
+
+        // Once the wait is over, one probe is let through at a time.
+        lock(&breaker.state).retry = Some(Instant::now());
+        assert!(breaker.admit().is_ok());
+        assert!(breaker.admit().is_err());
+        assert!(!breaker.record(true));
+        assert_eq!(gauge(), 0.0);
+        assert!(breaker.admit().is_ok());
+
+        drop(breaker);
+        assert!(OPEN.remove_label_values(&["fake:orders-replica"]).is_err());
+    }
+}
@@ -0,0 +1,217 @@
+use getopts::Options;
+use log::LevelFilter;
+use std::net::{Ipv4Addr, SocketAddr};
+
+pub const DEFAULT_CONFIG: &str = "example.toml";
+pub const DEFAULT_LISTEN_ADDRESS: &str = "127.0.0.1:9898";
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Command {
+    /// Run the scheduler and serve `/metrics`.
+    Serve,
+    /// Load and validate the configuration, then exit.
+    Check,
+    /// Run every query once and print the exposition text.
+    Once,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Args {
+    pub command: Command,
+    pub config: String,
+    pub listen_address: SocketAddr,
+    pub log_level: LevelFilter,
+    /// File holding the bearer token that `POST /-/reload` requires. The
+    /// endpoint is disabled without one.
+    pub reload_token_file: Option<String>,
+    /// Reload the configuration whenever its file changes.
+    pub watch_config: bool,
+}
+
+/// What the command line asks for.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Invocation {
+    Run(Args),
+    Help(String),
+    Version,
+}
+
+fn options() -> Options {
+    let mut options = Options::new();
+    options.optopt(
+        "c",
+        "config",
+        &format!(
+            "path to the configuration file (default {})",
+            DEFAULT_CONFIG
+        ),
+        "FILE",
+    );
+    options.optopt(
+        "",
+        "web.listen-address",
+        &format!(
+            "address to serve metrics on, e.g. :9898 (default {})",
+            DEFAULT_LISTEN_ADDRESS
+        ),
+        "ADDRESS",
+    );
+    options.optopt(
+        "",
+        "web.reload-token-file",
+        "file with the bearer token that enables POST /-/reload",
+        "FILE",
+    );
+    options.optflag(
+        "",
+        "config.watch",
+        "reload the configuration when its file changes",
+    );
+    options.optopt(
+        "",
+        "log-level",
+        "one of error, warn, info, debug or trace (default info)",
+        "LEVEL",
+    );
+    options.optflag("", "version", "print the version and exit");
+    options.optflag("h", "help", "print this help and exit");
+    options
+}
+
+pub fn usage(program: &str) -> String {
+    let brief = format!(
+        "Usage: {} [options] [serve|check|once]\n\n\
+         Commands:\n    \
+         serve   run the queries on their schedules and serve /metrics (default)\n    \
+         check   validate the configuration and exit\n    \
+         once    run every query once and print the metrics",
+        program
+    );
+    options().usage(&brief)
+}
+
+/// Parses the arguments following the program name.
+pub fn parse(program: &str, args: &[String]) -> Result<Invocation, String> {
+    let matches = options().parse(args).map_err(|err| err.to_string())?;
+
+    if matches.opt_present("help") {
+        return Ok(Invocation::Help(usage(program)));
+    }
+    if matches.opt_present("version") {
+        return Ok(Invocation::Version);
+    }
+
+    let command = match matches.free.as_slice() {
+        [] => Command::Serve,
+        [command] => match command.as_str() {
+            "serve" => Command::Serve,
+            "check" => Command::Check,
+            "once" => Command::Once,
+            other => return Err(format!("unknown command {:?}", other)),
+        },
+        [_, extra, ..] => return Err(format!("unexpected argument {:?}", extra)),
+    };
+
+    let listen_address = matches
+        .opt_str("web.listen-address")
+        .unwrap_or_else(|| DEFAULT_LISTEN_ADDRESS.to_string());
+    let log_level = matches
+        .opt_str("log-level")
+        .unwrap_or_else(|| String::from("info"));
+
+    Ok(Invocation::Run(Args {
+        command,
+        config: matches
+            .opt_str("config")
+            .unwrap_or_else(|| DEFAULT_CONFIG.to_string()),
+        listen_address: parse_listen_address(&listen_address)?,
+        log_level: log_level
+            .parse()
+            .map_err(|_| format!("invalid log level {:?}", log_level))?,
+        reload_token_file: matches.opt_str("web.reload-token-file"),
+        watch_config: matches.opt_present("config.watch"),
+    }))
+}
+
+/// Accepts `host:port` as well as `:port`, which listens on all interfaces.
+fn parse_listen_address(address: &str) -> Result<SocketAddr, String> {
+    let parsed = match address.strip_prefix(':') {
+        Some(port) => port
+            .parse()
+            .ok()
+            .map(|port| SocketAddr::from((Ipv4Addr::UNSPECIFIED, port))),
+        None => address.parse().ok(),
+    };
+    parsed.ok_or_else(|| format!("invalid listen address {:?}", address))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(args: &[&str]) -> Result<Args, String> {
+        let args = args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        match parse("jikji", &args)? {
+            Invocation::Run(args) => Ok(args),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn defaults_to_serve() {
+        assert_eq!(
+            run(&[]),
+            Ok(Args {
+                command: Command::Serve,
+                config: String::from(DEFAULT_CONFIG),
+                listen_address: DEFAULT_LISTEN_ADDRESS.parse().unwrap(),
+                log_level: LevelFilter::Info,
+                reload_token_file: None,
+                watch_config: false,
+            })
+        );
+    }
+
+    #[test]
+    fn parses_options_and_command() {
+        let args = run(&[
+            "--config",
+            "/etc/jikji.toml",
+            "--web.listen-address",
+            ":9100",
+            "--log-level",
+            "debug",
+            "--web.reload-token-file",
+            "/etc/jikji/token",
+            "--config.watch",
+            "check",
+        ])
+        .unwrap();
+        assert_eq!(args.command, Command::Check);
+        assert_eq!(args.config, "/etc/jikji.toml");
+        assert_eq!(args.listen_address, "0.0.0.0:9100".parse().unwrap());
+        assert_eq!(args.log_level, LevelFilter::Debug);
+        assert_eq!(args.reload_token_file.as_deref(), Some("/etc/jikji/token"));
+        assert!(args.watch_config);
+    }
+
+    #[test]
+    fn rejects_unknown_command() {
+        assert!(run(&["restart"]).is_err());
+        assert!(run(&["once", "serve"]).is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_values() {
+        assert!(run(&["--web.listen-address", "localhost"]).is_err());
+        assert!(run(&["--log-level", "loud"]).is_err());
+    }
+
+    #[test]
+    fn prints_version_and_help() {
+        let args = vec![String::from("--version")];
+        assert_eq!(parse("jikji", &args), Ok(Invocation::Version));
+        let args = vec![String::from("-h")];
+        assert!(matches!(parse("jikji", &args), Ok(Invocation::Help(_))));
+    }
+}
@@ -0,0 +1,326 @@
+use super::{json, web, Driver, Error, Rows, Source, Value};
+use crate::config::Database;
+use crate::dsn;
+use crate::tls::{self, Tls};
+use ureq::Agent;
+use url::Url;
+
+/// The format results are requested in, unless a query names another.
+const FORMAT: &str = "TSVWithNamesAndTypes";
+
+pub struct Clickhouse;
+
+impl Driver for Clickhouse {
+    fn validate(&self, database: &Database) -> Result<(), String> {
+        if database.dsn.is_none() && database.hostname.is_none() {
+            return Err(String::from("`hostname` is required without a `dsn`"));
+        }
+        settings(database)
+            .map(|_| ())
+            .map_err(|err| err.to_string())
+    }
+
+    fn connect(&self, database: &Database) -> Result<Box<dyn Source>, Error> {
+        let (url, username, tls) = settings(database)?;
+        let password = match url.password() {
+            Some(password) => Some(dsn::decode(password)),
+            None => database
+                .password()
+                .map_err(|err| Error::Settings(format!("cannot read password: {}", err)))?,
+        };
+        let authorization = match (username, password) {
+            (None, None) => None,
+            (username, password) => Some(web::basic(
+                username.as_deref().unwrap_or("default"),
+                password.as_deref(),
+            )),
+        };
+        let agent = web::agent(&tls, database.timeout)?;
+
+        let mut url = url;
+        let _ = url.set_username("");
+        let _ = url.set_password(None);
+        url.query_pairs_mut().append_pair("default_format", FORMAT);
+
+        Ok(Box::new(Connection {
+            agent,
+            url,
+            authorization,
+        }))
+    }
+
+    fn reaches_on_connect(&self) -> bool {
+        // Requests are only sent with queries.
+        false
+    }
+}
+
+/// The URL of the HTTP interface of `database`, its user name and its TLS
+/// settings. The `dsn` is a URL such as `https://jikji@db:8443/?database=app`
+/// whose settings take precedence over the separate fields. Without one,
+/// HTTPS is used if `sslmode` requires TLS. Without an `sslmode`, HTTPS
+/// verifies the server fully, rather than not at all like libpq's `prefer`.
+fn settings(database: &Database) -> Result<(Url, Option<String>, Tls), Error> {
+    let mut tls = Tls {
+        mode: database.sslmode.unwrap_or_default(),
+        rootcert: database.sslrootcert.clone(),
+        cert: database.sslcert.clone(),
+        key: database.sslkey.clone(),
+    };
+    let invalid = |err: url::ParseError| Error::Settings(format!("invalid dsn: {}", err));
+
+    let mut sslmode = database.sslmode.is_some();
+
+    let mut url = match &database.dsn {
+        Some(dsn) => {
+            let (dsn, parameters) = dsn::extract(dsn, &tls::PARAMETERS);
+            for (name, value) in parameters {
+                sslmode |= name == "sslmode";
+                tls.set(&name, value).map_err(Error::Settings)?;
+            }
+            Url::parse(&dsn).map_err(invalid)?
+        }
+        None => {
+            let (scheme, port) = match tls.mode {
+                tls::SslMode::Disable | tls::SslMode::Prefer => ("http", 8123),
+                _ => ("https", 8443),
+            };
+            let hostname = database.hostname.as_deref().unwrap_or_default();
+            let host = if hostname.contains(':') {
+                format!("[{}]", hostname)
+            } else {
+                hostname.to_string()
+            };
+            let port = database.port.unwrap_or(port);
+            Url::parse(&format!("{}://{}:{}/", scheme, host, port)).map_err(invalid)?
+        }
+    };
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(Error::Settings(format!(
+            "the clickhouse driver needs an http or https dsn, not {}",
+            url.scheme()
+        )));
+    }
+    if !sslmode {
+        tls.mode = tls::SslMode::VerifyFull;
+    }
+    tls.validate().map_err(Error::Settings)?;
+
+    let username = match url.username() {
+        "" => database.username.clone(),
+        username => Some(dsn::decode(username)),
+    };
+    if let Some(name) = &database.database {
+        if !url.query_pairs().any(|(key, _)| key == "database") {
+            url.query_pairs_mut().append_pair("database", name);
+        }
+    }
+    Ok((url, username, tls))
+}
+
+pub struct Connection {
+    agent: Agent,
+    url: Url,
+    authorization: Option<String>,
+}
+
+impl Source for Connection {
+    fn query(&mut self, query: &str) -> Result<Rows, Error> {
+        let mut request = self.agent.request_url("POST", &self.url);
+        if let Some(authorization) = &self.authorization {
+            request = request.set("Authorization", authorization);
+        }
+
+        let response = web::send(request, query)?;
+        let format = response
+            .header("X-ClickHouse-Format")
+            .unwrap_or(FORMAT)
+            .to_string();
+        let body = web::read(response)?;
+
+        match format.as_str() {
+            "TSVWithNamesAndTypes" | "TabSeparatedWithNamesAndTypes" => tsv(&body),
+            "JSONEachRow" => json::lines(&body),
+            other => Err(Error::Driver(
+                format!(
+                    "unsupported format {}, use {} or JSONEachRow",
+                    other, FORMAT
+                )
+                .into(),
+            )),
+        }
+    }
+}
+
+/// Parses a result in the `TSVWithNamesAndTypes` format.
+fn tsv(body: &str) -> Result<Rows, Error> {
+    let mut lines = body
+        .lines()
+        .map(|line| line.split('\t').collect::<Vec<_>>());
+    let (columns, types): (Vec<String>, Vec<String>) = match (lines.next(), lines.next()) {
+        (Some(names), Some(types)) => (
+            names.into_iter().map(unescape).collect(),
+            types.into_iter().map(unescape).collect(),
+        ),
+        _ => return Ok(Rows::default()),
+    };
+
+    let rows = lines
+        .map(|fields| {
+            if fields.len() != columns.len() {
+                return Err(Error::Driver(
+                    format!(
+                        "row has {} fields for {} columns",
+                        fields.len(),
+                        columns.len()
+                    )
+                    .into(),
+                ));
+            }
+            fields
+                .into_iter()
+                .zip(columns.iter().zip(&types))
+                .map(|(field, (name, kind))| value(field, name, kind))
+                .collect()
+        })
+        .collect::<Result<_, _>>()?;
+    Ok(Rows { columns, rows })
+}
+
+/// Undoes the backslash escapes of a TSV field.
+fn unescape(field: &str) -> String {
+    let mut unescaped = String::with_capacity(field.len());
+    let mut chars = field.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.next_if(|_| c == '\\')) {
+            (_, Some('t')) => unescaped.push('\t'),
+            (_, Some('n')) => unescaped.push('\n'),
+            (_, Some('r')) => unescaped.push('\r'),
+            (_, Some('0')) => unescaped.push('\0'),
+            (_, Some('b')) => unescaped.push('\u{8}'),
+            (_, Some('f')) => unescaped.push('\u{c}'),
+            (_, Some(escaped)) => unescaped.push(escaped),
+            (c, None) => unescaped.push(c),
+        }
+    }
+    unescaped
+}
+
+/// Converts a TSV field of the ClickHouse type `kind`, as it was sent: `\N`
+/// is `NULL`, whereas an escaped backslash followed by `N` is text.
+fn value(field: &str, name: &str, kind: &str) -> Result<Value, Error> {
+    let mut base = kind;
+    for wrapper in ["LowCardinality(", "Nullable("] {
+        if let Some(inner) = base.strip_prefix(wrapper) {
+            base = inner.strip_suffix(')').unwrap_or(inner);
+        }
+    }
+
+    if field == "\\N" {
+        return Ok(Value::Null);
+    }
+    let field = unescape(field);
+    let number = ["Int", "UInt", "Float", "Decimal"]
+        .iter()
+        .any(|prefix| base.starts_with(prefix));
+    match base {
+        _ if number => field.parse().map(Value::Number).map_err(|_| {
+            Error::Driver(format!("column {:?} has invalid number {:?}", name, field).into())
+        }),
+        "Bool" => Ok(Value::Number(if field == "true" { 1.0 } else { 0.0 })),
+        "String" | "UUID" | "IPv4" | "IPv6" => Ok(Value::Text(field)),
+        _ if base.starts_with("FixedString") || base.starts_with("Enum") => Ok(Value::Text(field)),
+        _ => Err(Error::UnsupportedType {
+            column: name.to_string(),
+            kind: kind.to_string(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn database(fields: &str) -> Database {
+        toml::from_str(&format!(
+            "driver = \"clickhouse\"\nmetrics = []\n{}",
+            fields
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn queries_over_http() {
+        let (port, server) = web::serve(
+            "X-ClickHouse-Format: TSVWithNamesAndTypes",
+            "event\tcount\tratio\nString\tUInt64\tNullable(Float64)\nsign\\tup\t12\t0.5\nlogin\t3\t\\N\n",
+        );
+        let mut source = Clickhouse
+            .connect(&database(&format!(
+                "hostname = \"127.0.0.1\"\nport = {}\nusername = \"jikji\"\npassword = \"s3cret\"\ndatabase = \"analytics\"",
+                port
+            )))
+            .unwrap();
+        let rows = source.query("select event, count() from events").unwrap();
+        let request = server.join().unwrap();
+
+        assert!(
+            request.starts_with("POST /?database=analytics&default_format=TSVWithNamesAndTypes ")
+        );
+        assert!(request.contains("Authorization: Basic amlramk6czNjcmV0\r\n"));
+        assert!(request.ends_with("select event, count() from events"));
+        assert_eq!(rows.columns, vec!["event", "count", "ratio"]);
+        assert_eq!(
+            rows.rows,
+            vec![
+                vec![
+                    Value::Text(String::from("sign\tup")),
+                    Value::Number(12.0),
+                    Value::Number(0.5)
+                ],
+                vec![
+                    Value::Text(String::from("login")),
+                    Value::Number(3.0),
+                    Value::Null
+                ],
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_tsv() {
+        let rows = tsv("name\tcount\nString\tNullable(UInt64)\n\\\\N\t\\N\n").unwrap();
+        assert_eq!(
+            rows.rows,
+            vec![vec![Value::Text(String::from("\\N")), Value::Null]]
+        );
+        assert!(tsv("name\tcount\nString\tUInt64\nlogin\n").is_err());
+        assert!(tsv("name\tcount\nString\tUInt64\nlogin\t3\t4\n").is_err());
+    }
+
+    #[test]
+    fn prefers_dsn_settings_over_fields() {
+        let (url, username, tls) = settings(&database(
+            "dsn = \"https://jikji@db:8443/?database=app&sslmode=verify-full\"\n\
+             hostname = \"other\"\nusername = \"other\"\ndatabase = \"other\"",
+        ))
+        .unwrap();
+        assert_eq!(url.as_str(), "https://jikji@db:8443/?database=app");
+        assert_eq!(username.as_deref(), Some("jikji"));
+        assert_eq!(tls.mode, tls::SslMode::VerifyFull);
+    }
+
+    #[test]
+    fn verifies_https_servers_unless_sslmode_says_otherwise() {
+        let (url, _, tls) = settings(&database("dsn = \"https://db:8443/\"")).unwrap();
+        assert_eq!(url.scheme(), "https");
+        assert!(tls.verifies_hostname());
+        assert!(tls.connector().is_ok());
+
+        let (_, _, tls) =
+            settings(&database("dsn = \"https://db:8443/?sslmode=require\"")).unwrap();
+        assert!(!tls.verifies_certificate());
+        let (url, _, _) = settings(&database("hostname = \"db\"")).unwrap();
+        assert_eq!(url.as_str(), "http://db:8123/");
+    }
+}
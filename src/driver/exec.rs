@@ -0,0 +1,357 @@
+//! A source that runs local commands. A metric's `query` is a command line,
+//! run by `sh -c`, whose standard output is parsed in the metric's `format`.
+//! A command that exits with an error fails the query with what it wrote to
+//! standard error.
+
+use super::{json, Driver, Error, Rows, Source, Value};
+use crate::config::{Database, Format, Metric};
+use std::io::{self, Read};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::{Duration, Instant};
+use wait_timeout::ChildExt;
+
+/// How long a command may run without a `timeout`.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+#[cfg(unix)]
+const SHELL: [&str; 2] = ["sh", "-c"];
+#[cfg(not(unix))]
+const SHELL: [&str; 2] = ["cmd", "/C"];
+
+pub struct Exec;
+
+impl Driver for Exec {
+    fn validate(&self, database: &Database) -> Result<(), String> {
+        match database
+            .metrics
+            .iter()
+            .find(|metric| metric.query.trim().is_empty())
+        {
+            Some(metric) => Err(format!("metric {} has no command", metric.describe())),
+            None => Ok(()),
+        }
+    }
+
+    fn connect(&self, database: &Database) -> Result<Box<dyn Source>, Error> {
+        Ok(Box::new(Shell {
+            timeout: database.timeout.unwrap_or(TIMEOUT),
+        }))
+    }
+}
+
+pub struct Shell {
+    timeout: Duration,
+}
+
+impl Shell {
+    fn run(&self, command: &str, format: Format) -> Result<Rows, Error> {
+        let output = run(command, self.timeout)?;
+        match format {
+            Format::Csv => csv(&output),
+            Format::Json => json::lines(&output),
+            Format::Prometheus => prometheus(&output),
+        }
+    }
+}
+
+impl Source for Shell {
+    fn query(&mut self, query: &str) -> Result<Rows, Error> {
+        self.run(query, Format::default())
+    }
+
+    fn collect(&mut self, metric: &Metric) -> Result<Rows, Error> {
+        self.run(&metric.query, metric.format.unwrap_or_default())
+    }
+}
+
+/// Runs `command` and returns its standard output, killing it and whatever
+/// it started if it takes longer than `timeout`.
+fn run(command: &str, timeout: Duration) -> Result<String, Error> {
+    let deadline = Instant::now() + timeout;
+    let mut shell = Command::new(SHELL[0]);
+    shell
+        .args([SHELL[1], command])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut shell, 0);
+    let mut child = shell
+        .spawn()
+        .map_err(|err| Error::Driver(format!("cannot run {}: {}", SHELL[0], err).into()))?;
+
+    // Read both pipes while the command runs, so that it never blocks on a
+    // full one.
+    let stdout = drain(child.stdout.take());
+    let stderr = drain(child.stderr.take());
+    let timed_out = || Error::Driver(format!("timed out after {:?}", timeout).into());
+
+    let status = match child.wait_timeout(timeout) {
+        Ok(Some(status)) => status,
+        Ok(None) => {
+            kill(&mut child);
+            return Err(timed_out());
+        }
+        Err(err) => {
+            kill(&mut child);
+            return Err(Error::Driver(Box::new(err)));
+        }
+    };
+    // A process the command left behind may still hold the pipes open.
+    let mut read = |output: Receiver<io::Result<Vec<u8>>>| match output
+        .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+    {
+        Ok(Ok(output)) => Ok(String::from_utf8_lossy(&output).into_owned()),
+        Ok(Err(err)) => Err(Error::Driver(Box::new(err))),
+        Err(_) => {
+            kill(&mut child);
+            Err(timed_out())
+        }
+    };
+    let stdout = read(stdout)?;
+    let stderr = read(stderr)?;
+
+    if !status.success() {
+        return Err(Error::Driver(
+            match stderr.trim() {
+                "" => status.to_string(),
+                stderr => format!("{}: {}", status, stderr),
+            }
+            .into(),
+        ));
+    }
+    Ok(stdout)
+}
+
+/// Reads all of `pipe` on a thread of its own.
+fn drain(pipe: Option<impl Read + Send + 'static>) -> Receiver<io::Result<Vec<u8>>> {
+    let (sender, receiver) = mpsc::channel();
+    if let Some(mut pipe) = pipe {
+        thread::spawn(move || {
+            let mut output = Vec::new();
+            let _ = sender.send(pipe.read_to_end(&mut output).map(|_| output));
+        });
+    }
+    receiver
+}
+
+/// Kills the command along with its process group, then reaps it.
+fn kill(child: &mut Child) {
+    #[cfg(unix)]
+    unsafe {
+        // The group's id is the command's, as it leads the group.
+        libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+    }
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+/// Parses comma-separated values under a header line. Empty fields are
+/// `NULL`, and fields holding numbers are used as numbers later.
+fn csv(output: &str) -> Result<Rows, Error> {
+    let driver_error = |err: csv::Error| Error::Driver(Box::new(err));
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(output.as_bytes());
+    let columns = reader
+        .headers()
+        .map_err(driver_error)?
+        .iter()
+        .map(String::from)
+        .collect();
+
+    let rows = reader
+        .records()
+        .map(|record| {
+            Ok(record
+                .map_err(driver_error)?
+                .iter()
+                .map(|field| match field {
+                    "" => Value::Null,
+                    field => Value::Text(field.to_string()),
+                })
+                .collect())
+        })
+        .collect::<Result<_, Error>>()?;
+    Ok(Rows { columns, rows })
+}
+
+/// Parses the Prometheus text format. Each sample is a row of its `name`, its
+/// labels and its `value`, with the labels other samples have `NULL`.
+/// Comments, and with them the metrics' types, are skipped.
+fn prometheus(output: &str) -> Result<Rows, Error> {
+    let mut labels = Vec::<String>::new();
+    let mut samples = Vec::new();
+    for line in output.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let sample = sample(line)
+            .ok_or_else(|| Error::Driver(format!("invalid sample {:?}", line).into()))?;
+        for (label, _) in &sample.1 {
+            if !labels.contains(label) {
+                labels.push(label.clone());
+            }
+        }
+        samples.push(sample);
+    }
+
+    let rows = samples
+        .into_iter()
+        .map(|(name, values, value)| {
+            let mut row = vec![Value::Null; labels.len() + 2];
+            row[0] = Value::Text(name);
+            for (label, text) in values {
+                if let Some(i) = labels.iter().position(|other| *other == label) {
+                    row[i + 1] = Value::Text(text);
+                }
+            }
+            row[labels.len() + 1] = Value::Number(value);
+            row
+        })
+        .collect();
+
+    let mut columns = vec![String::from("name")];
+    columns.append(&mut labels);
+    columns.push(String::from("value"));
+    Ok(Rows { columns, rows })
+}
+
+type Sample = (String, Vec<(String, String)>, f64);
+
+/// Splits a sample line such as `disk_free{volume="backup"} 1.5e9` into its
+/// name, labels and value. A timestamp after the value is ignored.
+fn sample(line: &str) -> Option<Sample> {
+    let end = line.find(|c: char| c == '{' || c.is_whitespace())?;
+    let (name, mut rest) = line.split_at(end);
+    if name.is_empty() {
+        return None;
+    }
+
+    let mut labels = Vec::new();
+    if let Some(inner) = rest.strip_prefix('{') {
+        rest = inner;
+        loop {
+            rest = rest.trim_start();
+            if let Some(after) = rest.strip_prefix('}') {
+                rest = after;
+                break;
+            }
+            let (label, after) = rest.split_once('=')?;
+            let (value, after) = quoted(after.trim_start())?;
+            labels.push((label.trim().to_string(), value));
+            let after = after.trim_start();
+            rest = after.strip_prefix(',').unwrap_or(after);
+        }
+    }
+
+    // Rust parses `NaN`, `+Inf` and `-Inf` like Prometheus writes them.
+    let value = rest.split_whitespace().next()?.parse().ok()?;
+    Some((name.to_string(), labels, value))
+}
+
+/// Reads a double-quoted label value and returns it with what follows it.
+fn quoted(text: &str) -> Option<(String, &str)> {
+    let inner = text.strip_prefix('"')?;
+    let mut value = String::new();
+    let mut chars = inner.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Some((value, &inner[i + 1..])),
+            '\\' => match chars.next()?.1 {
+                'n' => value.push('\n'),
+                escaped => value.push(escaped),
+            },
+            c => value.push(c),
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shell(timeout: Duration) -> Shell {
+        Shell { timeout }
+    }
+
+    #[test]
+    fn parses_csv_output() {
+        let rows = shell(TIMEOUT)
+            .run(
+                "printf 'volume, free\\nbackup, 12.5\\nscratch,\\n'",
+                Format::Csv,
+            )
+            .unwrap();
+        assert_eq!(rows.columns, vec!["volume", "free"]);
+        assert_eq!(
+            rows.rows,
+            vec![
+                vec![
+                    Value::Text(String::from("backup")),
+                    Value::Text(String::from("12.5"))
+                ],
+                vec![Value::Text(String::from("scratch")), Value::Null],
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_prometheus_output() {
+        let rows = prometheus(
+            "# TYPE backup_age_seconds gauge\n\
+             backup_age_seconds{host=\"db1\",kind=\"full\"} 3600 1700000000000\n\
+             backup_age_seconds{host=\"db \\\"2\\\"\", } +Inf\n\
+             backup_running 0\n",
+        )
+        .unwrap();
+        assert_eq!(rows.columns, vec!["name", "host", "kind", "value"]);
+        assert_eq!(
+            rows.rows,
+            vec![
+                vec![
+                    Value::Text(String::from("backup_age_seconds")),
+                    Value::Text(String::from("db1")),
+                    Value::Text(String::from("full")),
+                    Value::Number(3600.0)
+                ],
+                vec![
+                    Value::Text(String::from("backup_age_seconds")),
+                    Value::Text(String::from("db \"2\"")),
+                    Value::Null,
+                    Value::Number(f64::INFINITY)
+                ],
+                vec![
+                    Value::Text(String::from("backup_running")),
+                    Value::Null,
+                    Value::Null,
+                    Value::Number(0.0)
+                ],
+            ]
+        );
+        assert!(prometheus("backup_age_seconds{host=\"db1} 1\n").is_err());
+    }
+
+    #[test]
+    fn reports_failures_with_stderr() {
+        let err = shell(TIMEOUT)
+            .run("echo 'no such volume' >&2; exit 3", Format::Csv)
+            .err()
+            .unwrap();
+        assert_eq!(err.to_string(), "exit status: 3: no such volume");
+    }
+
+    #[test]
+    fn kills_commands_that_time_out() {
+        let started = Instant::now();
+        let err = shell(Duration::from_millis(200))
+            .run("sleep 5 & sleep 5", Format::Csv)
+            .err()
+            .unwrap();
+        assert!(err.to_string().starts_with("timed out"));
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+}
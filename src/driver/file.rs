@@ -0,0 +1,312 @@
+//! A source that queries CSV and Parquet files with SQL. On every collection
+//! the files are loaded into an in-memory SQLite database, one table per file
+//! named after it without its extension, so that `reports/sales.csv` is
+//! queried as `sales`. `path` is a file or a directory whose `.csv` and
+//! `.parquet` files are all loaded.
+
+use super::{Driver, Error, Source};
+use crate::config::Database;
+use parquet::file::reader::{FileReader, SerializedFileReader};
+use parquet::record::Field;
+use rusqlite::types::Value as SqlValue;
+use rusqlite::Connection;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+pub struct Files;
+
+impl Driver for Files {
+    fn validate(&self, database: &Database) -> Result<(), String> {
+        match &database.path {
+            Some(_) => Ok(()),
+            None => Err(String::from("the file driver needs a `path`")),
+        }
+    }
+
+    fn connect(&self, database: &Database) -> Result<Box<dyn Source>, Error> {
+        let path = Path::new(database.path.as_deref().unwrap_or_default());
+        let mut connection = Connection::open_in_memory().map_err(driver_error)?;
+
+        let mut tables: Vec<(String, PathBuf)> = Vec::new();
+        for file in files(path)? {
+            let name = file
+                .file_stem()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned();
+            if let Some((_, other)) = tables.iter().find(|(table, _)| *table == name) {
+                return Err(Error::Settings(format!(
+                    "{} and {} would both be table {}",
+                    other.display(),
+                    file.display(),
+                    name
+                )));
+            }
+            let table = match extension(&file).as_deref() {
+                Some("parquet") => parquet(&file),
+                _ => csv(&file),
+            }
+            .map_err(|err| Error::Driver(format!("{}: {}", file.display(), err).into()))?;
+            load(&mut connection, &name, table).map_err(driver_error)?;
+            tables.push((name, file));
+        }
+
+        connection
+            .pragma_update(None, "query_only", true)
+            .map_err(driver_error)?;
+        Ok(Box::new(connection))
+    }
+
+    fn reusable(&self) -> bool {
+        // The files are loaded when connecting.
+        false
+    }
+}
+
+fn driver_error(err: rusqlite::Error) -> Error {
+    Error::Driver(Box::new(err))
+}
+
+/// The column names and rows of a file.
+type Table = (Vec<String>, Vec<Vec<SqlValue>>);
+
+fn extension(path: &Path) -> Option<String> {
+    path.extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+}
+
+/// `path` itself if it is a file, or the CSV and Parquet files in it, in
+/// the order of their names.
+fn files(path: &Path) -> Result<Vec<PathBuf>, Error> {
+    let error = |err: std::io::Error| Error::Driver(format!("{}: {}", path.display(), err).into());
+    if !fs::metadata(path).map_err(error)?.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+
+    let mut files = Vec::new();
+    for entry in fs::read_dir(path).map_err(error)? {
+        let file = entry.map_err(error)?.path();
+        if matches!(extension(&file).as_deref(), Some("csv" | "parquet")) && file.is_file() {
+            files.push(file);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Creates the table `name` and inserts `rows` into it. Its columns have no
+/// type, so that every value keeps the one it was read with.
+fn load(connection: &mut Connection, name: &str, (columns, rows): Table) -> rusqlite::Result<()> {
+    let quote = |name: &str| format!("\"{}\"", name.replace('"', "\"\""));
+    let names = columns
+        .iter()
+        .map(|column| quote(column))
+        .collect::<Vec<_>>();
+    let placeholders = vec!["?"; columns.len()];
+
+    let transaction = connection.transaction()?;
+    transaction.execute(
+        &format!("create table {} ({})", quote(name), names.join(", ")),
+        [],
+    )?;
+    {
+        let mut insert = transaction.prepare(&format!(
+            "insert into {} values ({})",
+            quote(name),
+            placeholders.join(", ")
+        ))?;
+        for row in rows {
+            insert.execute(rusqlite::params_from_iter(row))?;
+        }
+    }
+    transaction.commit()
+}
+
+/// Reads a CSV file with a header line. Empty fields are `NULL` and fields
+/// that hold numbers are numbers.
+fn csv(path: &Path) -> Result<Table, Box<dyn std::error::Error + Send + Sync>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_path(path)?;
+    let columns = reader.headers()?.iter().map(String::from).collect();
+
+    let mut rows = Vec::new();
+    for record in reader.records() {
+        let row = record?
+            .iter()
+            .map(|field| match (field.parse(), field.parse()) {
+                _ if field.is_empty() => SqlValue::Null,
+                (Ok(integer), _) => SqlValue::Integer(integer),
+                (_, Ok(real)) => SqlValue::Real(real),
+                _ => SqlValue::Text(field.to_string()),
+            })
+            .collect();
+        rows.push(row);
+    }
+    Ok((columns, rows))
+}
+
+/// Reads a Parquet file's top-level columns. Timestamps become Unix times in
+/// seconds, dates `YYYY-MM-DD` text, and nested values their text form.
+fn parquet(path: &Path) -> Result<Table, Box<dyn std::error::Error + Send + Sync>> {
+    let reader = SerializedFileReader::new(File::open(path)?)?;
+    let columns = reader
+        .metadata()
+        .file_metadata()
+        .schema()
+        .get_fields()
+        .iter()
+        .map(|field| field.name().to_string())
+        .collect();
+
+    let mut rows = Vec::new();
+    for row in reader {
+        let row = row?
+            .get_column_iter()
+            .map(|(_, field)| value(field))
+            .collect();
+        rows.push(row);
+    }
+    Ok((columns, rows))
+}
+
+fn value(field: &Field) -> SqlValue {
+    match field {
+        Field::Null => SqlValue::Null,
+        Field::Bool(v) => SqlValue::Integer((*v).into()),
+        Field::Byte(v) => SqlValue::Integer((*v).into()),
+        Field::Short(v) => SqlValue::Integer((*v).into()),
+        Field::Int(v) => SqlValue::Integer((*v).into()),
+        Field::Long(v) => SqlValue::Integer(*v),
+        Field::UByte(v) => SqlValue::Integer((*v).into()),
+        Field::UShort(v) => SqlValue::Integer((*v).into()),
+        Field::UInt(v) => SqlValue::Integer((*v).into()),
+        Field::ULong(v) => i64::try_from(*v).map_or(SqlValue::Real(*v as f64), SqlValue::Integer),
+        Field::Float16(v) => SqlValue::Real(v.to_f64()),
+        Field::Float(v) => SqlValue::Real((*v).into()),
+        Field::Double(v) => SqlValue::Real(*v),
+        Field::Decimal(_) => field
+            .to_string()
+            .parse()
+            .map_or(SqlValue::Null, SqlValue::Real),
+        Field::Str(v) => SqlValue::Text(v.clone()),
+        Field::TimestampMillis(v) => SqlValue::Real(*v as f64 / 1e3),
+        Field::TimestampMicros(v) => SqlValue::Real(*v as f64 / 1e6),
+        other => SqlValue::Text(other.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::driver::Value;
+    use parquet::data_type::{ByteArrayType, DoubleType, Int64Type};
+    use parquet::file::writer::SerializedFileWriter;
+    use parquet::schema::parser::parse_message_type;
+    use std::env;
+    use std::sync::Arc;
+
+    fn database(path: &Path) -> Database {
+        toml::from_str(&format!(
+            "driver = \"file\"\npath = {:?}\nmetrics = []",
+            path.display().to_string()
+        ))
+        .unwrap()
+    }
+
+    fn directory(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("jikji-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir(&path).unwrap();
+        path
+    }
+
+    fn write_parquet(path: &Path) {
+        let schema = parse_message_type(
+            "message orders {
+                REQUIRED BYTE_ARRAY region (UTF8);
+                OPTIONAL INT64 orders;
+                REQUIRED DOUBLE revenue;
+            }",
+        )
+        .unwrap();
+        let mut writer = SerializedFileWriter::new(
+            File::create(path).unwrap(),
+            Arc::new(schema),
+            Default::default(),
+        )
+        .unwrap();
+        let mut group = writer.next_row_group().unwrap();
+
+        let mut column = group.next_column().unwrap().unwrap();
+        column
+            .typed::<ByteArrayType>()
+            .write_batch(&["eu".into(), "us".into()], None, None)
+            .unwrap();
+        column.close().unwrap();
+        let mut column = group.next_column().unwrap().unwrap();
+        column
+            .typed::<Int64Type>()
+            .write_batch(&[12], Some(&[1, 0]), None)
+            .unwrap();
+        column.close().unwrap();
+        let mut column = group.next_column().unwrap().unwrap();
+        column
+            .typed::<DoubleType>()
+            .write_batch(&[1250.5, 80.0], None, None)
+            .unwrap();
+        column.close().unwrap();
+
+        group.close().unwrap();
+        writer.close().unwrap();
+    }
+
+    #[test]
+    fn queries_csv_and_parquet_files() {
+        let path = directory("files");
+        fs::write(
+            path.join("refunds.csv"),
+            "region, amount, reason\neu, 120, damaged\neu, 15.5,\nus, 300, late\n",
+        )
+        .unwrap();
+        write_parquet(&path.join("orders.parquet"));
+        fs::write(path.join("notes.txt"), "not a table").unwrap();
+
+        let mut source = Files.connect(&database(&path)).unwrap();
+        let rows = source
+            .query(
+                "select o.region, o.orders, o.revenue - coalesce(sum(r.amount), 0) as net
+                 from orders o left join refunds r on r.region = o.region and r.amount > 100
+                 group by o.region order by o.region",
+            )
+            .unwrap();
+        assert!(source.query("delete from orders").is_err());
+        fs::remove_dir_all(&path).unwrap();
+
+        assert_eq!(rows.columns, vec!["region", "orders", "net"]);
+        assert_eq!(
+            rows.rows,
+            vec![
+                vec![
+                    Value::Text(String::from("eu")),
+                    Value::Number(12.0),
+                    Value::Number(1130.5)
+                ],
+                vec![
+                    Value::Text(String::from("us")),
+                    Value::Null,
+                    Value::Number(-220.0)
+                ],
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_the_file_that_failed() {
+        let path = directory("broken");
+        fs::write(path.join("sales.parquet"), "not parquet").unwrap();
+        let err = Files.connect(&database(&path)).err().unwrap();
+        fs::remove_dir_all(&path).unwrap();
+        assert!(err.to_string().contains("sales.parquet: "));
+    }
+}
@@ -0,0 +1,102 @@
+//! Rows from JSON documents, for the drivers whose results are JSON.
+
+use super::{Error, Rows, Value};
+use serde_json::Map;
+
+/// Converts a JSON scalar. Booleans are 1 or 0 and arrays and objects are
+/// unsupported; strings holding numbers are used as numbers later.
+pub fn value(value: &serde_json::Value, column: &str) -> Result<Value, Error> {
+    match value {
+        serde_json::Value::Null => Ok(Value::Null),
+        serde_json::Value::Bool(v) => Ok(Value::Number(if *v { 1.0 } else { 0.0 })),
+        serde_json::Value::Number(v) => Ok(Value::Number(v.as_f64().unwrap_or(f64::NAN))),
+        serde_json::Value::String(v) => Ok(Value::Text(v.clone())),
+        other => Err(Error::UnsupportedType {
+            column: column.to_string(),
+            kind: String::from(if other.is_array() { "array" } else { "object" }),
+        }),
+    }
+}
+
+/// Parses one JSON object per line, as ClickHouse's `JSONEachRow`.
+#[cfg(any(feature = "clickhouse", feature = "exec"))]
+pub fn lines(body: &str) -> Result<Rows, Error> {
+    let objects = body
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(serde_json::from_str)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| Error::Driver(Box::new(err)))?;
+    rows(&objects)
+}
+
+/// One row per object. The keys of all objects name the columns, in the
+/// order they first appear in, and objects may leave some of them out.
+pub fn rows<'a>(
+    objects: impl IntoIterator<Item = &'a Map<String, serde_json::Value>>,
+) -> Result<Rows, Error> {
+    let objects = objects.into_iter().collect::<Vec<_>>();
+    let mut columns: Vec<String> = Vec::new();
+    for key in objects.iter().flat_map(|object| object.keys()) {
+        if !columns.contains(key) {
+            columns.push(key.clone());
+        }
+    }
+
+    let rows = objects
+        .iter()
+        .map(|object| {
+            columns
+                .iter()
+                .map(|column| match object.get(column) {
+                    Some(v) => value(v, column),
+                    None => Ok(Value::Null),
+                })
+                .collect()
+        })
+        .collect::<Result<_, _>>()?;
+
+    Ok(Rows { columns, rows })
+}
+
+#[cfg(all(test, any(feature = "clickhouse", feature = "exec")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_one_object_per_line() {
+        let rows =
+            lines("{\"event\":\"login\",\"count\":\"3\",\"ok\":true}\n{\"event\":\"logout\"}\n")
+                .unwrap();
+        assert_eq!(rows.columns, vec!["event", "count", "ok"]);
+        assert_eq!(
+            rows.rows,
+            vec![
+                vec![
+                    Value::Text(String::from("login")),
+                    Value::Text(String::from("3")),
+                    Value::Number(1.0)
+                ],
+                vec![
+                    Value::Text(String::from("logout")),
+                    Value::Null,
+                    Value::Null
+                ],
+            ]
+        );
+    }
+
+    #[test]
+    fn takes_columns_from_every_object() {
+        let rows = lines("{}\n{\"a\": 1}\n{\"b\": \"x\", \"a\": 2}\n").unwrap();
+        assert_eq!(rows.columns, vec!["a", "b"]);
+        assert_eq!(
+            rows.rows,
+            vec![
+                vec![Value::Null, Value::Null],
+                vec![Value::Number(1.0), Value::Null],
+                vec![Value::Number(2.0), Value::Text(String::from("x"))],
+            ]
+        );
+    }
+}
@@ -0,0 +1,207 @@
+use crate::config::{Database, Metric};
+use serde::{Deserialize, Deserializer};
+use std::error;
+use std::fmt;
+use std::time::Duration;
+
+#[cfg(feature = "clickhouse")]
+pub mod clickhouse;
+#[cfg(feature = "exec")]
+pub mod exec;
+#[cfg(test)]
+pub mod fake;
+#[cfg(feature = "file")]
+pub mod file;
+#[cfg(feature = "http")]
+pub mod http;
+#[cfg(any(feature = "clickhouse", feature = "exec", feature = "http"))]
+mod json;
+#[cfg(feature = "mssql")]
+pub mod mssql;
+#[cfg(feature = "mysql")]
+pub mod mysql;
+#[cfg(feature = "postgres")]
+pub mod postgres;
+#[cfg(feature = "redis")]
+pub mod redis;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+#[cfg(any(feature = "clickhouse", feature = "http"))]
+mod web;
+
+/// A single cell of a query result.
+// Without any driver, nothing produces values.
+#[cfg_attr(not(driver), allow(dead_code))]
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Null,
+    Number(f64),
+    Text(String),
+}
+
+/// The result of a metric query: column names and one `Vec` per row.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Rows {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<Value>>,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// Connecting or querying failed.
+    #[cfg_attr(not(driver), allow(dead_code))]
+    Driver(Box<dyn error::Error + Send + Sync>),
+    /// A query could not reach the database, for drivers that connect with
+    /// every query.
+    #[cfg_attr(
+        not(any(test, feature = "clickhouse", feature = "http")),
+        allow(dead_code)
+    )]
+    Unreachable(Box<dyn error::Error + Send + Sync>),
+    /// The database's settings cannot be used, e.g. a password file is
+    /// missing.
+    #[cfg(settings)]
+    Settings(String),
+    /// Connecting is not tried again yet, after `failures` attempts in a row
+    /// failed.
+    BackingOff {
+        failures: u32,
+        retry_in: Duration,
+    },
+    UnsupportedDriver(String),
+    #[cfg(driver)]
+    UnsupportedType {
+        column: String,
+        kind: String,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Driver(err) | Error::Unreachable(err) => write!(f, "{}", err),
+            #[cfg(settings)]
+            Error::Settings(err) => write!(f, "{}", err),
+            Error::BackingOff { failures, retry_in } => write!(
+                f,
+                "not reconnecting for {:.1?} after {} failed attempts",
+                retry_in, failures
+            ),
+            Error::UnsupportedDriver(driver) => write!(f, "unsupported driver {:?}", driver),
+            #[cfg(driver)]
+            Error::UnsupportedType { column, kind } => {
+                write!(f, "column {:?} has unsupported type {}", column, kind)
+            }
+        }
+    }
+}
+
+/// A kind of database that metrics can be queried from, selected by a
+/// database's `driver`.
+pub trait Driver: Sync {
+    /// Checks the settings of `database`, so mistakes surface when the
+    /// configuration is loaded rather than on the first query.
+    fn validate(&self, database: &Database) -> Result<(), String>;
+
+    /// Opens a new connection to `database`.
+    fn connect(&self, database: &Database) -> Result<Box<dyn Source>, Error>;
+
+    /// Whether a connection may run more than one collection. Drivers whose
+    /// connections are a snapshot of the data say no, so that every
+    /// collection sees the data as it is then.
+    fn reusable(&self) -> bool {
+        true
+    }
+
+    /// Whether `connect` reaches the database, so that connecting tells
+    /// whether it is up. Drivers that only prepare requests say no, and
+    /// their queries tell instead.
+    fn reaches_on_connect(&self) -> bool {
+        true
+    }
+}
+
+/// An open connection to a database.
+pub trait Source: Send {
+    /// Runs `query` and returns every row it produced.
+    fn query(&mut self, query: &str) -> Result<Rows, Error>;
+
+    /// Runs the query of `metric`. Sources whose results need more of the
+    /// metric than its query to become rows override this.
+    fn collect(&mut self, metric: &Metric) -> Result<Rows, Error> {
+        self.query(&metric.query)
+    }
+}
+
+/// The drivers compiled into jikji by name. Each has a cargo feature of the
+/// same name.
+const DRIVERS: &[(&str, &dyn Driver)] = &[
+    #[cfg(feature = "clickhouse")]
+    ("clickhouse", &clickhouse::Clickhouse),
+    #[cfg(feature = "exec")]
+    ("exec", &exec::Exec),
+    #[cfg(test)]
+    ("fake", &fake::Fake),
+    #[cfg(feature = "file")]
+    ("file", &file::Files),
+    #[cfg(feature = "http")]
+    ("http", &http::Http),
+    #[cfg(feature = "mssql")]
+    ("mssql", &mssql::Mssql),
+    #[cfg(feature = "mysql")]
+    ("mysql", &mysql::Mysql),
+    #[cfg(feature = "postgres")]
+    ("postgres", &postgres::Postgres),
+    #[cfg(feature = "redis")]
+    ("redis", &redis::Redis),
+    #[cfg(feature = "sqlite")]
+    ("sqlite", &sqlite::Sqlite),
+];
+
+/// Returns the driver called `name`, if it was compiled in.
+pub fn get(name: &str) -> Option<&'static dyn Driver> {
+    DRIVERS
+        .iter()
+        .find(|(driver, _)| *driver == name)
+        .map(|(_, driver)| *driver)
+}
+
+/// Opens a new connection to `database` with its driver.
+pub fn connect(database: &Database) -> Result<Box<dyn Source>, Error> {
+    match get(&database.driver) {
+        Some(driver) => driver.connect(database),
+        None => Err(Error::UnsupportedDriver(database.driver.clone())),
+    }
+}
+
+/// Deserializes the name of a compiled-in driver.
+pub fn name<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    let name = String::deserialize(deserializer)?;
+    if get(&name).is_some() {
+        return Ok(name);
+    }
+
+    let expected = DRIVERS
+        .iter()
+        .map(|(driver, _)| format!("`{}`", driver))
+        .collect::<Vec<_>>();
+    Err(serde::de::Error::custom(match expected.as_slice() {
+        [] => format!("unknown driver `{}`, jikji was built without drivers", name),
+        expected => format!(
+            "unknown driver `{}`, expected one of {}",
+            name,
+            expected.join(", ")
+        ),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_compiled_in_drivers() {
+        assert!(get("fake").is_some());
+        assert!(get("oracle").is_none());
+    }
+}
@@ -0,0 +1,283 @@
+use super::{Driver, Error, Rows, Source, Value};
+use crate::config::Database;
+use crate::tls::{self, Tls};
+use connection_string::AdoNetString;
+use tiberius::{Client, ColumnData, ColumnType, Config, SqlBrowser};
+use tokio::net::TcpStream;
+use tokio::runtime::{self, Runtime};
+use tokio_util::compat::{Compat, TokioAsyncWriteCompatExt};
+
+/// The ADO.NET keys, in any case, that tiberius accepts for each setting.
+const SERVER: &[&str] = &["server", "data source"];
+const USER: &[&str] = &["uid", "username", "user", "user id"];
+const PASSWORD: &[&str] = &["password", "pwd"];
+const DATABASE: &[&str] = &["database", "initial catalog", "databasename"];
+const ENCRYPTION: &[&str] = &[
+    "encrypt",
+    "trustservercertificate",
+    "trustservercertificateca",
+];
+
+pub struct Mssql;
+
+impl Driver for Mssql {
+    fn validate(&self, database: &Database) -> Result<(), String> {
+        if database.dsn.is_none() && database.hostname.is_none() {
+            return Err(String::from("`hostname` is required without a `dsn`"));
+        }
+        settings(database, None)
+            .map(|_| ())
+            .map_err(|err| err.to_string())
+    }
+
+    fn connect(&self, database: &Database) -> Result<Box<dyn Source>, Error> {
+        let password = database
+            .password()
+            .map_err(|err| Error::Settings(format!("cannot read password: {}", err)))?;
+        let (config, browse) = settings(database, password)?;
+
+        // Like the postgres crate, run the asynchronous client on a runtime
+        // of its own so that queries can block.
+        let runtime = runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(|err| Error::Driver(Box::new(err)))?;
+        let client = runtime
+            .block_on(open(config, browse))
+            .map_err(driver_error)?;
+        Ok(Box::new(Connection { runtime, client }))
+    }
+}
+
+fn driver_error(err: tiberius::error::Error) -> Error {
+    Error::Driver(Box::new(err))
+}
+
+/// The connection settings of `database` and whether its named instance must
+/// be looked up with the SQL Server Browser. The `dsn` is an ADO.NET
+/// connection string whose settings take precedence over the separate fields;
+/// libpq's TLS settings may be given in it too.
+fn settings(database: &Database, password: Option<String>) -> Result<(Config, bool), Error> {
+    let invalid = |err: &dyn std::fmt::Display| Error::Settings(format!("invalid dsn: {}", err));
+    let mut ado: AdoNetString = database
+        .dsn
+        .as_deref()
+        .unwrap_or_default()
+        .parse()
+        .map_err(|err| invalid(&err))?;
+
+    let mut tls = Tls {
+        mode: database.sslmode.unwrap_or_default(),
+        rootcert: database.sslrootcert.clone(),
+        cert: database.sslcert.clone(),
+        key: database.sslkey.clone(),
+    };
+    for name in tls::PARAMETERS {
+        if let Some(value) = ado.remove(name) {
+            tls.set(name, value).map_err(Error::Settings)?;
+        }
+    }
+    tls.validate().map_err(Error::Settings)?;
+    if tls.cert.is_some() {
+        return Err(Error::Settings(String::from(
+            "the mssql driver does not support `sslcert` and `sslkey`",
+        )));
+    }
+
+    // A named instance is written `host\instance`. With a port, it is
+    // connected to directly rather than looked up.
+    if let Some(hostname) = &database.hostname {
+        let server = match database.port {
+            Some(port) => format!("tcp:{},{}", hostname, port),
+            None => format!("tcp:{}", hostname),
+        };
+        fill(&mut ado, SERVER, server);
+    }
+    if let Some(username) = &database.username {
+        fill(&mut ado, USER, username.clone());
+    }
+    if let Some(password) = password {
+        fill(&mut ado, PASSWORD, password);
+    }
+    if let Some(name) = &database.database {
+        fill(&mut ado, DATABASE, name.clone());
+    }
+    if !ENCRYPTION.iter().any(|key| ado.contains_key(*key)) {
+        for (key, value) in encryption(&tls) {
+            ado.insert(key.to_string(), value);
+        }
+    }
+
+    let browse = SERVER
+        .iter()
+        .find_map(|key| ado.get(*key))
+        .is_some_and(|server| server.contains('\\') && !server.contains(','));
+    let config = Config::from_ado_string(&ado.to_string()).map_err(|err| invalid(&err))?;
+    Ok((config, browse))
+}
+
+/// Sets the setting known by `keys` unless the connection string has it.
+fn fill(ado: &mut AdoNetString, keys: &[&str], value: String) {
+    if !keys.iter().any(|key| ado.contains_key(*key)) {
+        ado.insert(keys[0].to_string(), value);
+    }
+}
+
+/// The ADO.NET settings for `tls`. TLS cannot be used without checking the
+/// host name of a verified certificate, so `verify-ca` is as strict as
+/// `verify-full`.
+fn encryption(tls: &Tls) -> Vec<(&'static str, String)> {
+    let trust_all = ("trustservercertificate", String::from("true"));
+    match (tls.mode, &tls.rootcert) {
+        (tls::SslMode::Disable, _) => vec![("encrypt", String::from("DANGER_PLAINTEXT"))],
+        // Only the login is always encrypted, the rest if the server asks.
+        (tls::SslMode::Prefer, _) => vec![("encrypt", String::from("false")), trust_all],
+        (_, Some(rootcert)) => vec![
+            ("encrypt", String::from("true")),
+            ("trustservercertificateca", rootcert.clone()),
+        ],
+        (_, None) if tls.verifies_certificate() => vec![("encrypt", String::from("true"))],
+        (_, None) => vec![("encrypt", String::from("true")), trust_all],
+    }
+}
+
+async fn open(mut config: Config, browse: bool) -> tiberius::Result<Client<Compat<TcpStream>>> {
+    let tcp = if browse {
+        TcpStream::connect_named(&config).await?
+    } else {
+        TcpStream::connect(config.get_addr()).await?
+    };
+    tcp.set_nodelay(true)?;
+
+    match Client::connect(config.clone(), tcp.compat_write()).await {
+        // Azure SQL redirects clients to the node that serves the database.
+        Err(tiberius::error::Error::Routing { host, port }) => {
+            config.host(host);
+            config.port(port);
+            let tcp = TcpStream::connect(config.get_addr()).await?;
+            tcp.set_nodelay(true)?;
+            Client::connect(config, tcp.compat_write()).await
+        }
+        client => client,
+    }
+}
+
+pub struct Connection {
+    runtime: Runtime,
+    client: Client<Compat<TcpStream>>,
+}
+
+impl Source for Connection {
+    fn query(&mut self, query: &str) -> Result<Rows, Error> {
+        let client = &mut self.client;
+        let (columns, rows) = self
+            .runtime
+            .block_on(async {
+                let mut stream = client.simple_query(query).await?;
+                let columns = stream
+                    .columns()
+                    .await?
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|column| (column.name().to_string(), column.column_type()))
+                    .collect::<Vec<_>>();
+                Ok((columns, stream.into_first_result().await?))
+            })
+            .map_err(driver_error)?;
+
+        let rows = rows
+            .into_iter()
+            .map(|row| {
+                row.into_iter()
+                    .zip(&columns)
+                    .map(|(data, (name, kind))| value(data, name, *kind))
+                    .collect::<Result<_, _>>()
+            })
+            .collect::<Result<_, _>>()?;
+        let columns = columns.into_iter().map(|(name, _)| name).collect();
+        Ok(Rows { columns, rows })
+    }
+}
+
+fn value(data: ColumnData<'static>, name: &str, kind: ColumnType) -> Result<Value, Error> {
+    let value = match data {
+        ColumnData::U8(v) => v.map(|v| Value::Number(v.into())),
+        ColumnData::I16(v) => v.map(|v| Value::Number(v.into())),
+        ColumnData::I32(v) => v.map(|v| Value::Number(v.into())),
+        ColumnData::I64(v) => v.map(|v| Value::Number(v as f64)),
+        ColumnData::F32(v) => v.map(|v| Value::Number(v.into())),
+        ColumnData::F64(v) => v.map(Value::Number),
+        ColumnData::Numeric(v) => v.map(|v| Value::Number(v.into())),
+        ColumnData::Bit(v) => v.map(|v| Value::Number(if v { 1.0 } else { 0.0 })),
+        ColumnData::String(v) => v.map(|v| Value::Text(v.into_owned())),
+        ColumnData::Guid(v) => v.map(|v| Value::Text(v.to_string())),
+        _ => {
+            return Err(Error::UnsupportedType {
+                column: name.to_string(),
+                kind: format!("{:?}", kind),
+            })
+        }
+    };
+
+    Ok(value.unwrap_or(Value::Null))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn database(fields: &str) -> Database {
+        toml::from_str(&format!("driver = \"mssql\"\nmetrics = []\n{}", fields)).unwrap()
+    }
+
+    #[test]
+    fn prefers_dsn_settings_over_fields() {
+        let (config, browse) = settings(
+            &database(
+                "dsn = \"Server=tcp:db,1500;User Id=jikji;Database=finance;sslmode=disable\"\n\
+                 hostname = \"other\"\nusername = \"other\"\nsslmode = \"verify-full\"",
+            ),
+            Some(String::from("s3cret")),
+        )
+        .unwrap();
+        assert_eq!(config.get_addr(), "db:1500");
+        assert!(!browse);
+    }
+
+    #[test]
+    fn connects_to_named_instances() {
+        let fields = "hostname = 'db\\FINANCE'\nusername = \"jikji\"";
+        let (config, browse) = settings(&database(fields), None).unwrap();
+        assert_eq!(config.get_addr(), "db:1434");
+        assert!(browse);
+
+        let (config, browse) =
+            settings(&database(&format!("{}\nport = 1500", fields)), None).unwrap();
+        assert_eq!(config.get_addr(), "db:1500");
+        assert!(!browse);
+    }
+
+    #[test]
+    fn maps_sslmode_to_encryption() {
+        let tls = |mode, rootcert: Option<&str>| Tls {
+            mode,
+            rootcert: rootcert.map(String::from),
+            ..Tls::default()
+        };
+        assert_eq!(
+            encryption(&tls(tls::SslMode::Disable, None)),
+            vec![("encrypt", String::from("DANGER_PLAINTEXT"))]
+        );
+        assert_eq!(
+            encryption(&tls(tls::SslMode::Require, Some("ca.pem"))),
+            vec![
+                ("encrypt", String::from("true")),
+                ("trustservercertificateca", String::from("ca.pem"))
+            ]
+        );
+        assert_eq!(
+            encryption(&tls(tls::SslMode::VerifyFull, None)),
+            vec![("encrypt", String::from("true"))]
+        );
+    }
+}
@@ -0,0 +1,253 @@
+use super::{Driver, Error, Rows, Source, Value};
+use crate::config::Database;
+use crate::dsn;
+use crate::tls::{self, Tls};
+use mysql::consts::ColumnType;
+use mysql::prelude::Queryable;
+use mysql::{Conn, DriverError, Opts, OptsBuilder, SslOpts};
+use std::path::PathBuf;
+
+pub struct Mysql;
+
+impl Driver for Mysql {
+    fn validate(&self, database: &Database) -> Result<(), String> {
+        if database.dsn.is_none() && database.hostname.is_none() {
+            return Err(String::from("`hostname` is required without a `dsn`"));
+        }
+        settings(database)
+            .map(|_| ())
+            .map_err(|err| err.to_string())
+    }
+
+    fn connect(&self, database: &Database) -> Result<Box<dyn Source>, Error> {
+        let (mut opts, tls) = settings(database)?;
+        if Opts::from(opts.clone()).get_pass().is_none() {
+            let password = database
+                .password()
+                .map_err(|err| Error::Settings(format!("cannot read password: {}", err)))?;
+            opts = opts.pass(password);
+        }
+
+        let connection = match Conn::new(opts.clone().ssl_opts(ssl_opts(&tls))) {
+            // Like libpq's `prefer`, fall back to plain text when the server
+            // has no TLS.
+            Err(mysql::Error::DriverError(DriverError::TlsNotSupported))
+                if tls.mode == tls::SslMode::Prefer =>
+            {
+                Conn::new(opts)
+            }
+            connection => connection,
+        }
+        .map_err(driver_error)?;
+        Ok(Box::new(connection))
+    }
+}
+
+fn driver_error(err: mysql::Error) -> Error {
+    Error::Driver(Box::new(err))
+}
+
+/// The connection settings of `database` other than its password. Settings
+/// in its `dsn` take precedence over the separate fields.
+fn settings(database: &Database) -> Result<(OptsBuilder, Tls), Error> {
+    let mut tls = Tls {
+        mode: database.sslmode.unwrap_or_default(),
+        rootcert: database.sslrootcert.clone(),
+        cert: database.sslcert.clone(),
+        key: database.sslkey.clone(),
+    };
+    let opts = match &database.dsn {
+        Some(dsn) => {
+            let (dsn, parameters) = dsn::extract(dsn, &tls::PARAMETERS);
+            for (name, value) in parameters {
+                tls.set(&name, value).map_err(Error::Settings)?;
+            }
+            let opts = Opts::from_url(&dsn).map_err(|err| Error::Driver(Box::new(err)))?;
+            let user = database
+                .username
+                .clone()
+                .filter(|_| opts.get_user().is_none());
+            let name = database
+                .database
+                .clone()
+                .filter(|_| opts.get_db_name().is_none());
+            let builder = OptsBuilder::from_opts(opts);
+            let builder = match user {
+                Some(user) => builder.user(Some(user)),
+                None => builder,
+            };
+            match name {
+                Some(name) => builder.db_name(Some(name)),
+                None => builder,
+            }
+        }
+        None => {
+            let hostname = database.hostname.clone().unwrap_or_default();
+            let builder = if hostname.starts_with('/') {
+                tls.mode = tls::SslMode::Disable;
+                OptsBuilder::new().socket(Some(hostname))
+            } else {
+                OptsBuilder::new().ip_or_hostname(Some(hostname))
+            };
+            builder
+                .tcp_port(database.port.unwrap_or(3306))
+                .user(database.username.clone())
+                .db_name(database.database.clone())
+        }
+    };
+
+    tls.validate().map_err(Error::Settings)?;
+    if tls.cert.is_some() {
+        return Err(Error::Settings(String::from(
+            "the mysql driver does not support `sslcert` and `sslkey`",
+        )));
+    }
+    // Connect to the server configured, not to a Unix domain socket it
+    // advertises on loopback connections.
+    Ok((opts.prefer_socket(false), tls))
+}
+
+/// The TLS options for `tls`, reading `rootcert` anew on every connection so
+/// that rotated certificates are picked up.
+fn ssl_opts(tls: &Tls) -> Option<SslOpts> {
+    if tls.mode == tls::SslMode::Disable {
+        return None;
+    }
+    Some(
+        SslOpts::default()
+            .with_root_cert_path(tls.rootcert.clone().map(PathBuf::from))
+            .with_danger_accept_invalid_certs(!tls.verifies_certificate())
+            .with_danger_skip_domain_validation(!tls.verifies_hostname()),
+    )
+}
+
+impl Source for Conn {
+    fn query(&mut self, query: &str) -> Result<Rows, Error> {
+        // The text protocol, unlike prepared statements, runs every `SHOW`
+        // statement.
+        let mut result = self.query_iter(query).map_err(driver_error)?;
+        let types = result
+            .columns()
+            .as_ref()
+            .iter()
+            .map(|column| (column.name_str().into_owned(), column.column_type()))
+            .collect::<Vec<_>>();
+
+        let mut rows = Vec::new();
+        for row in result.by_ref() {
+            let values = row
+                .map_err(driver_error)?
+                .unwrap()
+                .into_iter()
+                .zip(&types)
+                .map(|(cell, (name, kind))| value(cell, name, *kind))
+                .collect::<Result<_, _>>()?;
+            rows.push(values);
+        }
+
+        let columns = types.into_iter().map(|(name, _)| name).collect();
+        Ok(Rows { columns, rows })
+    }
+}
+
+fn value(cell: mysql::Value, name: &str, kind: ColumnType) -> Result<Value, Error> {
+    let bytes = match cell {
+        mysql::Value::NULL => return Ok(Value::Null),
+        mysql::Value::Bytes(bytes) => bytes,
+        other => {
+            return Err(Error::UnsupportedType {
+                column: name.to_string(),
+                kind: format!("{:?}", other),
+            })
+        }
+    };
+    let text = String::from_utf8_lossy(&bytes);
+
+    use ColumnType::*;
+    match kind {
+        MYSQL_TYPE_TINY
+        | MYSQL_TYPE_SHORT
+        | MYSQL_TYPE_INT24
+        | MYSQL_TYPE_LONG
+        | MYSQL_TYPE_LONGLONG
+        | MYSQL_TYPE_YEAR
+        | MYSQL_TYPE_FLOAT
+        | MYSQL_TYPE_DOUBLE
+        | MYSQL_TYPE_DECIMAL
+        | MYSQL_TYPE_NEWDECIMAL => match text.parse() {
+            Ok(number) => Ok(Value::Number(number)),
+            Err(_) => Err(Error::Driver(
+                format!("column {:?} has invalid number {:?}", name, text).into(),
+            )),
+        },
+        MYSQL_TYPE_VARCHAR
+        | MYSQL_TYPE_VAR_STRING
+        | MYSQL_TYPE_STRING
+        | MYSQL_TYPE_ENUM
+        | MYSQL_TYPE_SET
+        | MYSQL_TYPE_TINY_BLOB
+        | MYSQL_TYPE_MEDIUM_BLOB
+        | MYSQL_TYPE_LONG_BLOB
+        | MYSQL_TYPE_BLOB
+        | MYSQL_TYPE_JSON => Ok(Value::Text(text.into_owned())),
+        other => Err(Error::UnsupportedType {
+            column: name.to_string(),
+            kind: format!("{:?}", other),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn database(fields: &str) -> Database {
+        toml::from_str(&format!("driver = \"mysql\"\nmetrics = []\n{}", fields)).unwrap()
+    }
+
+    #[test]
+    fn prefers_dsn_settings_over_fields() {
+        let (opts, tls) = settings(&database(
+            "dsn = \"mysql://jikji@db:3307/app?sslmode=verify-full\"\n\
+             hostname = \"other\"\nusername = \"other\"\nsslmode = \"disable\"",
+        ))
+        .unwrap();
+        let opts = Opts::from(opts);
+        assert_eq!(opts.get_ip_or_hostname(), "db");
+        assert_eq!(opts.get_tcp_port(), 3307);
+        assert_eq!(opts.get_user(), Some("jikji"));
+        assert_eq!(opts.get_db_name(), Some("app"));
+        assert_eq!(tls.mode, tls::SslMode::VerifyFull);
+    }
+
+    #[test]
+    fn connects_to_unix_sockets_without_tls() {
+        let (opts, tls) = settings(&database(
+            "host = \"/run/mysqld/mysqld.sock\"\nusername = \"jikji\"\nsslmode = \"require\"",
+        ))
+        .unwrap();
+        assert_eq!(
+            Opts::from(opts).get_socket(),
+            Some("/run/mysqld/mysqld.sock")
+        );
+        assert_eq!(ssl_opts(&tls), None);
+    }
+
+    #[test]
+    fn maps_column_types() {
+        let bytes = |text: &str| mysql::Value::Bytes(text.as_bytes().to_vec());
+        assert_eq!(
+            value(bytes("12.50"), "size", ColumnType::MYSQL_TYPE_NEWDECIMAL).unwrap(),
+            Value::Number(12.5)
+        );
+        assert_eq!(
+            value(bytes("InnoDB"), "engine", ColumnType::MYSQL_TYPE_VAR_STRING).unwrap(),
+            Value::Text(String::from("InnoDB"))
+        );
+        assert_eq!(
+            value(mysql::Value::NULL, "size", ColumnType::MYSQL_TYPE_LONG).unwrap(),
+            Value::Null
+        );
+        assert!(value(bytes("2024-01-01"), "day", ColumnType::MYSQL_TYPE_DATE).is_err());
+    }
+}
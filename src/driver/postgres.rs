@@ -0,0 +1,281 @@
+use super::{Driver, Error, Rows, Source, Value};
+use crate::config::Database;
+use crate::dsn;
+use crate::pgpass;
+use crate::tls::{self, Tls};
+use postgres::config::{Host, SslMode};
+use postgres::types::{FromSql, Type};
+use postgres::{Client, Row};
+use postgres_native_tls::MakeTlsConnector;
+use std::error;
+
+pub struct Postgres;
+
+impl Driver for Postgres {
+    fn validate(&self, database: &Database) -> Result<(), String> {
+        if database.dsn.is_none() && database.hostname.is_none() {
+            return Err(String::from("`hostname` is required without a `dsn`"));
+        }
+        settings(database)
+            .map(|_| ())
+            .map_err(|err| err.to_string())
+    }
+
+    fn connect(&self, database: &Database) -> Result<Box<dyn Source>, Error> {
+        let (mut config, tls) = settings(database)?;
+        if config.get_password().is_none() {
+            let password = match database
+                .password()
+                .map_err(|err| Error::Settings(format!("cannot read password: {}", err)))?
+            {
+                Some(password) => Some(password),
+                None => pgpass(&config),
+            };
+            if let Some(password) = password {
+                config.password(password);
+            }
+        }
+
+        let connector = tls
+            .connector()
+            .map_err(|err| Error::Settings(format!("TLS: {}", err)))?;
+        let client = config
+            .connect(MakeTlsConnector::new(connector))
+            .map_err(driver_error)?;
+        Ok(Box::new(client))
+    }
+}
+
+fn driver_error(err: postgres::Error) -> Error {
+    Error::Driver(Box::new(err))
+}
+
+/// The connection settings of `database` other than its password. Settings
+/// in its `dsn` take precedence over the separate fields.
+fn settings(database: &Database) -> Result<(postgres::Config, Tls), Error> {
+    let mut tls = Tls {
+        mode: database.sslmode.unwrap_or_default(),
+        rootcert: database.sslrootcert.clone(),
+        cert: database.sslcert.clone(),
+        key: database.sslkey.clone(),
+    };
+    let mut config: postgres::Config = match &database.dsn {
+        Some(dsn) => {
+            let (dsn, parameters) = dsn::extract(dsn, &tls::PARAMETERS);
+            for (name, value) in parameters {
+                tls.set(&name, value).map_err(Error::Settings)?;
+            }
+            dsn.parse().map_err(driver_error)?
+        }
+        None => postgres::Config::new(),
+    };
+    if let (Some(hostname), []) = (&database.hostname, config.get_hosts()) {
+        config.host(hostname);
+    }
+    if let (Some(port), []) = (database.port, config.get_ports()) {
+        config.port(port);
+    }
+    // Like libpq, connect as the user jikji runs as by default, which is
+    // what peer authentication over a Unix domain socket expects.
+    if config.get_user().is_none() {
+        if let Some(username) = database
+            .username
+            .clone()
+            .or_else(|| whoami::fallible::username().ok())
+        {
+            config.user(&username);
+        }
+    }
+    if let (Some(name), None) = (&database.database, config.get_dbname()) {
+        config.dbname(name);
+    }
+
+    tls.validate().map_err(Error::Settings)?;
+    if config
+        .get_hosts()
+        .iter()
+        .all(|host| !matches!(host, Host::Tcp(_)))
+    {
+        config.ssl_mode(SslMode::Disable);
+    } else {
+        config.ssl_mode(ssl_mode(&tls));
+    }
+    Ok((config, tls))
+}
+
+/// The `sslmode` the postgres crate understands. It only decides whether TLS
+/// is used; verification is up to the connector.
+fn ssl_mode(tls: &Tls) -> SslMode {
+    match tls.mode {
+        tls::SslMode::Disable => SslMode::Disable,
+        tls::SslMode::Prefer => SslMode::Prefer,
+        _ => SslMode::Require,
+    }
+}
+
+/// Looks up the password for the first host of `config` in the libpq
+/// password file.
+fn pgpass(config: &postgres::Config) -> Option<String> {
+    let hostname = match config.get_hosts().first() {
+        Some(Host::Tcp(hostname)) => hostname.as_str(),
+        _ => "localhost",
+    };
+    let port = config.get_ports().first().copied().unwrap_or(5432);
+    let username = config.get_user()?;
+    let database = config.get_dbname().unwrap_or(username);
+    pgpass::lookup(hostname, port, database, username)
+}
+
+impl Source for Client {
+    fn query(&mut self, query: &str) -> Result<Rows, Error> {
+        let statement = self.prepare(query).map_err(driver_error)?;
+        let columns = statement
+            .columns()
+            .iter()
+            .map(|column| column.name().to_string())
+            .collect();
+
+        let rows = Client::query(self, &statement, &[])
+            .map_err(driver_error)?
+            .iter()
+            .map(values)
+            .collect::<Result<_, _>>()?;
+
+        Ok(Rows { columns, rows })
+    }
+}
+
+fn values(row: &Row) -> Result<Vec<Value>, Error> {
+    row.columns()
+        .iter()
+        .enumerate()
+        .map(|(index, column)| value(row, index, column.type_(), column.name()))
+        .collect()
+}
+
+fn value(row: &Row, index: usize, ty: &Type, name: &str) -> Result<Value, Error> {
+    let value = match *ty {
+        Type::INT2 => get::<i16>(row, index)?.map(|v| Value::Number(v.into())),
+        Type::INT4 => get::<i32>(row, index)?.map(|v| Value::Number(v.into())),
+        Type::INT8 => get::<i64>(row, index)?.map(|v| Value::Number(v as f64)),
+        Type::FLOAT4 => get::<f32>(row, index)?.map(|v| Value::Number(v.into())),
+        Type::FLOAT8 => get::<f64>(row, index)?.map(Value::Number),
+        Type::NUMERIC => get::<Numeric>(row, index)?.map(|v| Value::Number(v.0)),
+        Type::BOOL => get::<bool>(row, index)?.map(|v| Value::Number(if v { 1.0 } else { 0.0 })),
+        Type::TEXT | Type::VARCHAR | Type::BPCHAR | Type::NAME => {
+            get::<String>(row, index)?.map(Value::Text)
+        }
+        ref other => {
+            return Err(Error::UnsupportedType {
+                column: name.to_string(),
+                kind: other.to_string(),
+            })
+        }
+    };
+
+    Ok(value.unwrap_or(Value::Null))
+}
+
+fn get<'a, T: FromSql<'a>>(row: &'a Row, index: usize) -> Result<Option<T>, Error> {
+    row.try_get(index).map_err(driver_error)
+}
+
+/// A `NUMERIC` value, as `sum`, `avg` and since PostgreSQL 14 `extract`
+/// return, read as the nearest `f64`.
+struct Numeric(f64);
+
+impl<'a> FromSql<'a> for Numeric {
+    fn from_sql(_: &Type, raw: &'a [u8]) -> Result<Numeric, Box<dyn error::Error + Sync + Send>> {
+        // The number of base-10000 digits, the weight of the first one, the
+        // sign and the display scale, followed by the digits.
+        let field = |i: usize| {
+            raw.get(2 * i..2 * i + 2)
+                .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+                .ok_or("truncated numeric")
+        };
+        let digits = usize::from(field(0)?);
+        let weight = i32::from(field(1)? as i16);
+        let sign = field(2)?;
+
+        // Parsing the decimal digits once rounds a single time, where adding
+        // up the base-10000 digits in floating point would round at each.
+        let mut decimal = String::from("0");
+        for i in 0..digits {
+            match field(4 + i)? {
+                digit @ 0..=9999 => decimal.push_str(&format!("{:04}", digit)),
+                _ => return Err("invalid numeric digit".into()),
+            }
+        }
+        let exponent = 4 * (weight + 1 - digits as i32);
+        let magnitude: f64 = format!("{}e{}", decimal, exponent).parse()?;
+
+        match sign {
+            0x0000 => Ok(Numeric(magnitude)),
+            0x4000 => Ok(Numeric(-magnitude)),
+            0xC000 => Ok(Numeric(f64::NAN)),
+            0xD000 => Ok(Numeric(f64::INFINITY)),
+            0xF000 => Ok(Numeric(f64::NEG_INFINITY)),
+            _ => Err("invalid numeric sign".into()),
+        }
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        *ty == Type::NUMERIC
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn database(fields: &str) -> Database {
+        toml::from_str(&format!("driver = \"postgres\"\nmetrics = []\n{}", fields)).unwrap()
+    }
+
+    fn numeric(words: &[u16]) -> f64 {
+        let raw: Vec<u8> = words.iter().flat_map(|word| word.to_be_bytes()).collect();
+        Numeric::from_sql(&Type::NUMERIC, &raw).unwrap().0
+    }
+
+    #[test]
+    fn reads_numerics() {
+        // 12345678.9, as `sum(amount)` may return it.
+        assert_eq!(numeric(&[3, 1, 0x0000, 1, 1234, 5678, 9000]), 12345678.9);
+        assert_eq!(numeric(&[1, 0xffff, 0x4000, 1, 1000]), -0.1);
+        assert_eq!(numeric(&[1, 2, 0x0000, 0, 7]), 7e8);
+        assert_eq!(numeric(&[0, 0, 0x0000, 0]), 0.0);
+        // 1 / 3::numeric, to 20 decimal places.
+        assert_eq!(
+            numeric(&[5, 0xffff, 0x0000, 20, 3333, 3333, 3333, 3333, 3333]),
+            1.0 / 3.0
+        );
+        assert_eq!(numeric(&[0, 0, 0xD000, 0]), f64::INFINITY);
+        assert!(numeric(&[0, 0, 0xC000, 0]).is_nan());
+        assert!(Numeric::from_sql(&Type::NUMERIC, &[0, 2, 0, 0, 0, 0, 0, 0, 0, 1]).is_err());
+        assert!(Numeric::from_sql(&Type::NUMERIC, &[0, 1, 0, 0, 0, 0, 0, 0, 0x27, 0x10]).is_err());
+    }
+
+    #[test]
+    fn connects_to_unix_socket_directories_without_tls() {
+        let (config, _) = settings(&database(
+            "host = \"/var/run/postgresql\"\nusername = \"jikji\"\nsslmode = \"require\"",
+        ))
+        .unwrap();
+        assert!(matches!(config.get_hosts(), [Host::Unix(path)] if path.ends_with("postgresql")));
+        assert_eq!(config.get_ssl_mode(), SslMode::Disable);
+    }
+
+    #[test]
+    fn prefers_dsn_settings_over_fields() {
+        let (config, tls) = settings(&database(
+            "dsn = \"postgres://jikji@db:6432/app?sslmode=verify-full\"\n\
+             hostname = \"other\"\nusername = \"other\"\nsslmode = \"disable\"",
+        ))
+        .unwrap();
+        assert!(matches!(config.get_hosts(), [Host::Tcp(host)] if host == "db"));
+        assert_eq!(config.get_ports(), [6432]);
+        assert_eq!(config.get_user(), Some("jikji"));
+        assert_eq!(tls.mode, tls::SslMode::VerifyFull);
+        assert_eq!(config.get_ssl_mode(), SslMode::Require);
+    }
+}
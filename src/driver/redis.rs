@@ -0,0 +1,556 @@
+//! A source that runs Redis commands. A metric's `query` is a command such
+//! as `ZCOUNT jobs:delayed -inf +inf`, quoted like a shell command line. If
+//! the first argument is a key pattern, the keys matching it are found with
+//! `SCAN` and the command runs once for each:
+//!
+//! * with `*`, `?` or `[...]` wildcards, e.g. `LLEN queue:*`, every key is a
+//!   row of its `value` and `key`;
+//! * with placeholders, e.g. `LLEN queue:{queue}:pending`, the text each one
+//!   matches is a column of the same name too. `{{` and `}}` stand for
+//!   literal braces, such as those of hash tags.
+//!
+//! Other replies are a row per element of an array, or a single row of their
+//! `value`. `INFO` is the exception: its reply is one row with a column per
+//! field.
+
+use super::{Driver, Error, Rows, Source, Value};
+use crate::config::Database;
+use crate::dsn;
+use crate::tls::{self, Tls};
+use redis::{ConnectionAddr, ConnectionInfo, IntoConnectionInfo, RedisConnectionInfo};
+use std::path::PathBuf;
+
+/// How many commands are sent at once when a pattern matches many keys.
+const BATCH: usize = 1000;
+
+pub struct Redis;
+
+impl Driver for Redis {
+    fn validate(&self, database: &Database) -> Result<(), String> {
+        if database.dsn.is_none() && database.hostname.is_none() {
+            return Err(String::from("`hostname` is required without a `dsn`"));
+        }
+        settings(database).map_err(|err| err.to_string())?;
+        for metric in &database.metrics {
+            Command::parse(&metric.query)
+                .map_err(|err| format!("metric {}: {}", metric.describe(), err))?;
+        }
+        Ok(())
+    }
+
+    fn connect(&self, database: &Database) -> Result<Box<dyn Source>, Error> {
+        let mut info = settings(database)?;
+        if info.redis.password.is_none() {
+            info.redis.password = database
+                .password()
+                .map_err(|err| Error::Settings(format!("cannot read password: {}", err)))?;
+        }
+
+        let connection = redis::Client::open(info)
+            .and_then(|client| client.get_connection())
+            .map_err(driver_error)?;
+        Ok(Box::new(connection))
+    }
+}
+
+fn driver_error(err: redis::RedisError) -> Error {
+    Error::Driver(Box::new(err))
+}
+
+/// The connection settings of `database` other than its password. The `dsn`
+/// is a URL such as `rediss://jikji@cache:6380/2` whose settings take
+/// precedence over the separate fields. Its scheme decides whether TLS is
+/// used; without a `dsn`, it is used if `sslmode` requires it.
+fn settings(database: &Database) -> Result<ConnectionInfo, Error> {
+    let mut tls = Tls {
+        mode: database.sslmode.unwrap_or_default(),
+        rootcert: database.sslrootcert.clone(),
+        cert: database.sslcert.clone(),
+        key: database.sslkey.clone(),
+    };
+    let mut sslmode = database.sslmode.is_some();
+
+    let mut info = match &database.dsn {
+        Some(dsn) => {
+            let (dsn, parameters) = dsn::extract(dsn, &tls::PARAMETERS);
+            for (name, value) in parameters {
+                sslmode |= name == "sslmode";
+                tls.set(&name, value).map_err(Error::Settings)?;
+            }
+            dsn.as_str().into_connection_info().map_err(driver_error)?
+        }
+        None => {
+            let hostname = database.hostname.clone().unwrap_or_default();
+            let port = database.port.unwrap_or(6379);
+            let addr = match tls.mode {
+                _ if hostname.starts_with('/') => ConnectionAddr::Unix(PathBuf::from(hostname)),
+                tls::SslMode::Disable | tls::SslMode::Prefer => ConnectionAddr::Tcp(hostname, port),
+                _ => ConnectionAddr::TcpTls {
+                    host: hostname,
+                    port,
+                    insecure: false,
+                    tls_params: None,
+                },
+            };
+            ConnectionInfo {
+                addr,
+                redis: RedisConnectionInfo::default(),
+            }
+        }
+    };
+
+    // A `rediss` URL asks for TLS, which like any client of such URLs
+    // verifies the server unless `sslmode` says otherwise.
+    if !sslmode {
+        tls.mode = tls::SslMode::VerifyFull;
+    }
+    tls.validate().map_err(Error::Settings)?;
+    if tls.rootcert.is_some() || tls.cert.is_some() {
+        return Err(Error::Settings(String::from(
+            "the redis driver does not support `sslrootcert`, `sslcert` and `sslkey`",
+        )));
+    }
+    if let ConnectionAddr::TcpTls { host, port, .. } = info.addr {
+        info.addr = ConnectionAddr::TcpTls {
+            host,
+            port,
+            insecure: !tls.verifies_certificate(),
+            tls_params: None,
+        };
+        if tls.verifies_certificate() && !tls.verifies_hostname() {
+            info.addr.set_danger_accept_invalid_hostnames(true);
+        }
+    }
+
+    if info.redis.username.is_none() {
+        info.redis.username = database.username.clone();
+    }
+    if let (0, Some(name)) = (info.redis.db, &database.database) {
+        info.redis.db = name.parse().map_err(|_| {
+            Error::Settings(format!("the redis `database` is a number, not {:?}", name))
+        })?;
+    }
+    Ok(info)
+}
+
+/// A metric's command.
+#[derive(Debug, PartialEq)]
+struct Command {
+    name: String,
+    key: Option<Key>,
+    /// The arguments after the first.
+    arguments: Vec<String>,
+}
+
+/// The first argument of a command, which usually is a key.
+#[derive(Debug, PartialEq)]
+enum Key {
+    Literal(String),
+    Pattern(Pattern),
+}
+
+/// A pattern matching keys.
+#[derive(Debug, PartialEq)]
+struct Pattern {
+    /// The pattern for `SCAN`, with `*` in place of the placeholders.
+    glob: String,
+    /// The names of the placeholders.
+    placeholders: Vec<String>,
+    /// The text before, between and after the placeholders.
+    literals: Vec<String>,
+}
+
+impl Command {
+    fn parse(query: &str) -> Result<Command, String> {
+        let mut words = shlex::split(query)
+            .ok_or_else(|| String::from("the command has an unterminated quote"))?
+            .into_iter();
+        let name = words
+            .next()
+            .ok_or_else(|| String::from("the query needs a command"))?;
+        let key = words.next().map(|key| Key::parse(&key)).transpose()?;
+        Ok(Command {
+            name,
+            key,
+            arguments: words.collect(),
+        })
+    }
+
+    fn run(&self, connection: &mut redis::Connection) -> Result<Rows, Error> {
+        let pattern = match &self.key {
+            Some(Key::Pattern(pattern)) => pattern,
+            key => {
+                let mut command = redis::cmd(&self.name);
+                if let Some(Key::Literal(key)) = key {
+                    command.arg(key);
+                }
+                let reply = command
+                    .arg(&self.arguments)
+                    .query(connection)
+                    .map_err(driver_error)?;
+                return match reply {
+                    redis::Value::BulkString(text) if self.name.eq_ignore_ascii_case("INFO") => {
+                        Ok(info(&String::from_utf8_lossy(&text)))
+                    }
+                    reply => rows(reply),
+                };
+            }
+        };
+
+        let mut keys = scan(connection, &pattern.glob)?;
+        keys.sort();
+        keys.dedup();
+        let mut rows = Vec::with_capacity(keys.len());
+        for batch in keys.chunks(BATCH) {
+            let mut pipeline = redis::pipe();
+            for key in batch {
+                pipeline.cmd(&self.name).arg(key).arg(&self.arguments);
+            }
+            let replies: Vec<redis::Value> = pipeline.query(connection).map_err(driver_error)?;
+
+            for (key, reply) in batch.iter().zip(replies) {
+                let key = String::from_utf8_lossy(key);
+                let Some(captures) = pattern.captures(&key) else {
+                    continue;
+                };
+                let mut row = vec![value(reply, "value")?, Value::Text(key.into_owned())];
+                row.extend(captures.into_iter().map(Value::Text));
+                rows.push(row);
+            }
+        }
+
+        let mut columns = vec![String::from("value"), String::from("key")];
+        columns.extend(pattern.placeholders.iter().cloned());
+        Ok(Rows { columns, rows })
+    }
+}
+
+impl Key {
+    fn parse(argument: &str) -> Result<Key, String> {
+        let mut literals = vec![String::new()];
+        let mut placeholders = Vec::new();
+        let mut chars = argument.chars().peekable();
+        while let Some(c) = chars.next() {
+            let literal = literals.last_mut().unwrap();
+            match c {
+                '{' if chars.next_if_eq(&'{').is_some() => literal.push('{'),
+                '}' if chars.next_if_eq(&'}').is_some() => literal.push('}'),
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) if c.is_ascii_alphanumeric() || c == '_' => name.push(c),
+                            _ => {
+                                return Err(format!(
+                                    "invalid placeholder in {:?}, write a literal `{{` as `{{{{`",
+                                    argument
+                                ))
+                            }
+                        }
+                    }
+                    if name.is_empty() || (literal.is_empty() && !placeholders.is_empty()) {
+                        return Err(format!("invalid placeholder in {:?}", argument));
+                    }
+                    placeholders.push(name);
+                    literals.push(String::new());
+                }
+                '}' => {
+                    return Err(format!(
+                        "unmatched `}}` in {:?}, write a literal `}}` as `}}}}`",
+                        argument
+                    ))
+                }
+                c => literal.push(c),
+            }
+        }
+
+        let wildcards = |text: &str| text.contains(['*', '?', '[', '\\']);
+        if placeholders.is_empty() {
+            let literal = literals.remove(0);
+            return Ok(match wildcards(&literal) {
+                true => Key::Pattern(Pattern {
+                    glob: literal,
+                    placeholders,
+                    literals: Vec::new(),
+                }),
+                false => Key::Literal(literal),
+            });
+        }
+        if literals.iter().any(|literal| wildcards(literal)) {
+            return Err(format!(
+                "{:?} cannot have both placeholders and wildcards",
+                argument
+            ));
+        }
+        Ok(Key::Pattern(Pattern {
+            glob: literals.join("*"),
+            placeholders,
+            literals,
+        }))
+    }
+}
+
+impl Pattern {
+    /// The text that each placeholder matches in `key`, or none if the key
+    /// does not match.
+    fn captures(&self, key: &str) -> Option<Vec<String>> {
+        let Some((first, literals)) = self.literals.split_first() else {
+            return Some(Vec::new());
+        };
+        let mut rest = key.strip_prefix(first.as_str())?;
+        let mut captures = Vec::new();
+        for (i, literal) in literals.iter().enumerate() {
+            let end = if i + 1 == literals.len() {
+                rest.strip_suffix(literal.as_str())?.len()
+            } else {
+                rest.find(literal.as_str())?
+            };
+            captures.push(rest[..end].to_string());
+            rest = &rest[end + literal.len()..];
+        }
+        Some(captures)
+    }
+}
+
+/// The keys matching `glob`, some of them possibly more than once.
+fn scan(connection: &mut redis::Connection, glob: &str) -> Result<Vec<Vec<u8>>, Error> {
+    let mut keys = Vec::new();
+    let mut cursor = 0;
+    loop {
+        let (next, mut batch): (u64, Vec<Vec<u8>>) = redis::cmd("SCAN")
+            .arg(cursor)
+            .arg("MATCH")
+            .arg(glob)
+            .arg("COUNT")
+            .arg(BATCH)
+            .query(connection)
+            .map_err(driver_error)?;
+        keys.append(&mut batch);
+        if next == 0 {
+            return Ok(keys);
+        }
+        cursor = next;
+    }
+}
+
+/// Parses the reply of `INFO` into one row with a column per field.
+fn info(text: &str) -> Rows {
+    let (columns, values) = text
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| line.trim_end().split_once(':'))
+        .map(|(field, value)| (field.to_string(), Value::Text(value.to_string())))
+        .unzip();
+    Rows {
+        columns,
+        rows: vec![values],
+    }
+}
+
+/// A row per element of an array or set reply, or a single row otherwise.
+fn rows(reply: redis::Value) -> Result<Rows, Error> {
+    let rows = match reply {
+        redis::Value::Array(elements) | redis::Value::Set(elements) => elements
+            .into_iter()
+            .map(|element| Ok(vec![value(element, "value")?]))
+            .collect::<Result<_, Error>>()?,
+        reply => vec![vec![value(reply, "value")?]],
+    };
+    Ok(Rows {
+        columns: vec![String::from("value")],
+        rows,
+    })
+}
+
+fn value(reply: redis::Value, column: &str) -> Result<Value, Error> {
+    match reply {
+        redis::Value::Nil => Ok(Value::Null),
+        redis::Value::Int(v) => Ok(Value::Number(v as f64)),
+        redis::Value::Double(v) => Ok(Value::Number(v)),
+        redis::Value::Boolean(v) => Ok(Value::Number(if v { 1.0 } else { 0.0 })),
+        redis::Value::BulkString(v) => Ok(Value::Text(String::from_utf8_lossy(&v).into_owned())),
+        redis::Value::SimpleString(v) => Ok(Value::Text(v)),
+        redis::Value::VerbatimString { text, .. } => Ok(Value::Text(text)),
+        redis::Value::Okay => Ok(Value::Text(String::from("OK"))),
+        other => Err(Error::UnsupportedType {
+            column: column.to_string(),
+            kind: format!("{:?}", other),
+        }),
+    }
+}
+
+impl Source for redis::Connection {
+    fn query(&mut self, query: &str) -> Result<Rows, Error> {
+        Command::parse(query).map_err(Error::Settings)?.run(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    fn database(fields: &str) -> Database {
+        toml::from_str(&format!("driver = \"redis\"\nmetrics = []\n{}", fields)).unwrap()
+    }
+
+    #[test]
+    fn parses_key_patterns() {
+        let command = Command::parse("ZCOUNT 'jobs:{queue}:{{shard}}' -inf +inf").unwrap();
+        assert_eq!(command.name, "ZCOUNT");
+        assert_eq!(command.arguments, vec!["-inf", "+inf"]);
+        let Some(Key::Pattern(pattern)) = command.key else {
+            panic!("{:?} is not a pattern", command.key);
+        };
+        assert_eq!(pattern.glob, "jobs:*:{shard}");
+        assert_eq!(pattern.placeholders, vec!["queue"]);
+        assert_eq!(
+            pattern.captures("jobs:mail:eu:{shard}"),
+            Some(vec![String::from("mail:eu")])
+        );
+        assert_eq!(pattern.captures("jobs:mail"), None);
+
+        assert_eq!(
+            Command::parse("LLEN queue:*").unwrap().key,
+            Some(Key::Pattern(Pattern {
+                glob: String::from("queue:*"),
+                placeholders: vec![],
+                literals: vec![],
+            }))
+        );
+        assert_eq!(
+            Command::parse("GET {{user}}.visits").unwrap().key,
+            Some(Key::Literal(String::from("{user}.visits")))
+        );
+        assert!(Command::parse("LLEN queue:{a}{b}").is_err());
+        assert!(Command::parse("LLEN queue:{name}:*").is_err());
+        assert!(Command::parse("LLEN queue:{name").is_err());
+    }
+
+    /// Answers the commands of one connection on a local port with `reply`.
+    fn serve(reply: fn(&[String]) -> &'static str) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 0 {
+                let mut command = Vec::new();
+                for _ in 0..line.trim()[1..].parse().unwrap() {
+                    line.clear();
+                    reader.read_line(&mut line).unwrap();
+                    line.clear();
+                    reader.read_line(&mut line).unwrap();
+                    command.push(line.trim_end().to_string());
+                }
+                stream.write_all(reply(&command).as_bytes()).unwrap();
+                line.clear();
+            }
+        });
+        port
+    }
+
+    #[test]
+    fn runs_commands_for_matching_keys() {
+        let port = serve(|command| match command {
+            [auth, password] if auth == "AUTH" && password == "s3cret" => "+OK\r\n",
+            [client, ..] if client == "CLIENT" => "+OK\r\n",
+            // SCAN may return a key more than once.
+            [scan, _, _, glob, ..] if scan == "SCAN" && glob == "queue:*:pending" => {
+                "*2\r\n$1\r\n0\r\n*3\r\n$17\r\nqueue:sms:pending\r\n\
+                 $18\r\nqueue:mail:pending\r\n$17\r\nqueue:sms:pending\r\n"
+            }
+            [llen, key] if llen == "LLEN" && key == "queue:mail:pending" => ":12\r\n",
+            [llen, key] if llen == "LLEN" && key == "queue:sms:pending" => ":3\r\n",
+            _ => "-ERR unexpected command\r\n",
+        });
+        let mut source = Redis
+            .connect(&database(&format!(
+                "hostname = \"127.0.0.1\"\nport = {}\npassword = \"s3cret\"",
+                port
+            )))
+            .unwrap();
+        let rows = source.query("LLEN queue:{queue}:pending").unwrap();
+
+        assert_eq!(rows.columns, vec!["value", "key", "queue"]);
+        assert_eq!(
+            rows.rows,
+            vec![
+                vec![
+                    Value::Number(12.0),
+                    Value::Text(String::from("queue:mail:pending")),
+                    Value::Text(String::from("mail"))
+                ],
+                vec![
+                    Value::Number(3.0),
+                    Value::Text(String::from("queue:sms:pending")),
+                    Value::Text(String::from("sms"))
+                ],
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_info_fields() {
+        let rows = info("# Clients\r\nconnected_clients:12\r\nblocked_clients:0\r\n\r\n# Keyspace\r\ndb0:keys=4,expires=1\r\n");
+        assert_eq!(
+            rows.columns,
+            vec!["connected_clients", "blocked_clients", "db0"]
+        );
+        assert_eq!(
+            rows.rows,
+            vec![vec![
+                Value::Text(String::from("12")),
+                Value::Text(String::from("0")),
+                Value::Text(String::from("keys=4,expires=1"))
+            ]]
+        );
+    }
+
+    #[test]
+    fn uses_tls_as_sslmode_says() {
+        let info = settings(&database(
+            "hostname = \"cache\"\nsslmode = \"verify-full\"\ndatabase = \"2\"",
+        ))
+        .unwrap();
+        assert_eq!(
+            info.addr,
+            ConnectionAddr::TcpTls {
+                host: String::from("cache"),
+                port: 6379,
+                insecure: false,
+                tls_params: None
+            }
+        );
+        assert_eq!(info.redis.db, 2);
+
+        let info = settings(&database(
+            "dsn = \"rediss://jikji@cache:6380/3\"\nusername = \"other\"\ndatabase = \"2\"",
+        ))
+        .unwrap();
+        assert!(matches!(
+            info.addr,
+            ConnectionAddr::TcpTls {
+                insecure: false,
+                port: 6380,
+                ..
+            }
+        ));
+        assert_eq!(info.redis.username.as_deref(), Some("jikji"));
+        assert_eq!(info.redis.db, 3);
+
+        let info = settings(&database("dsn = \"rediss://cache/?sslmode=require\"")).unwrap();
+        assert!(matches!(
+            info.addr,
+            ConnectionAddr::TcpTls { insecure: true, .. }
+        ));
+        let info = settings(&database("hostname = \"cache\"")).unwrap();
+        assert!(matches!(info.addr, ConnectionAddr::Tcp(..)));
+
+        assert!(settings(&database("hostname = \"cache\"\nsslrootcert = \"ca.pem\"")).is_err());
+    }
+}
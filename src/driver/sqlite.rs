@@ -0,0 +1,155 @@
+use super::{Driver, Error, Rows, Source, Value};
+use crate::config::{Database, OpenMode};
+use rusqlite::types::ValueRef;
+use rusqlite::{Connection, OpenFlags};
+use std::time::Duration;
+
+/// How long a query waits for a writer to release its lock.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub struct Sqlite;
+
+impl Driver for Sqlite {
+    fn validate(&self, database: &Database) -> Result<(), String> {
+        match &database.path {
+            Some(_) => Ok(()),
+            None => Err(String::from("the sqlite driver needs a `path`")),
+        }
+    }
+
+    fn connect(&self, database: &Database) -> Result<Box<dyn Source>, Error> {
+        let path = database.path.as_deref().unwrap_or_default();
+        let flags = OpenFlags::SQLITE_OPEN_URI | OpenFlags::SQLITE_OPEN_NO_MUTEX;
+
+        let connection = match database.mode.unwrap_or_default() {
+            OpenMode::ReadOnly => {
+                Connection::open_with_flags(path, flags | OpenFlags::SQLITE_OPEN_READ_ONLY)
+            }
+            OpenMode::Immutable => Connection::open_with_flags(
+                format!("file:{}?immutable=1", encode(path)),
+                flags | OpenFlags::SQLITE_OPEN_READ_ONLY,
+            ),
+            OpenMode::ReadWrite => {
+                Connection::open_with_flags(path, flags | OpenFlags::SQLITE_OPEN_READ_WRITE)
+            }
+        }
+        .map_err(driver_error)?;
+        connection
+            .busy_timeout(BUSY_TIMEOUT)
+            .map_err(driver_error)?;
+
+        Ok(Box::new(connection))
+    }
+}
+
+fn driver_error(err: rusqlite::Error) -> Error {
+    Error::Driver(Box::new(err))
+}
+
+/// Escapes the characters that have a meaning in an SQLite URI filename.
+fn encode(path: &str) -> String {
+    path.replace('%', "%25")
+        .replace('?', "%3f")
+        .replace('#', "%23")
+}
+
+impl Source for Connection {
+    fn query(&mut self, query: &str) -> Result<Rows, Error> {
+        let mut statement = self.prepare(query).map_err(driver_error)?;
+        let columns = statement
+            .column_names()
+            .into_iter()
+            .map(String::from)
+            .collect::<Vec<_>>();
+
+        let mut results = statement.query([]).map_err(driver_error)?;
+        let mut rows = Vec::new();
+        while let Some(row) = results.next().map_err(driver_error)? {
+            let values = (0..columns.len())
+                .map(|index| match row.get_ref(index).map_err(driver_error)? {
+                    ValueRef::Null => Ok(Value::Null),
+                    ValueRef::Integer(v) => Ok(Value::Number(v as f64)),
+                    ValueRef::Real(v) => Ok(Value::Number(v)),
+                    ValueRef::Text(v) => Ok(Value::Text(String::from_utf8_lossy(v).into_owned())),
+                    ValueRef::Blob(_) => Err(Error::UnsupportedType {
+                        column: columns[index].clone(),
+                        kind: String::from("blob"),
+                    }),
+                })
+                .collect::<Result<_, _>>()?;
+            rows.push(values);
+        }
+
+        Ok(Rows { columns, rows })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    fn database(path: &str, mode: &str) -> Database {
+        toml::from_str(&format!(
+            "driver = \"sqlite\"\npath = {:?}\nmode = {:?}\nmetrics = []",
+            path, mode
+        ))
+        .unwrap()
+    }
+
+    fn create(name: &str) -> String {
+        let path = env::temp_dir().join(format!("jikji-{}-{}.db", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        let connection = Connection::open(&path).unwrap();
+        connection
+            .execute_batch(
+                "create table jobs (queue text, state text, runtime real);
+                 insert into jobs values ('mail', 'done', 1.5), ('mail', null, 2), ('sms', 'done', 0.5);",
+            )
+            .unwrap();
+        path.display().to_string()
+    }
+
+    #[test]
+    fn queries_rows() {
+        let path = create("query");
+        let mut source = Sqlite.connect(&database(&path, "read-only")).unwrap();
+        let rows = source
+            .query("select queue, count(state) as done, sum(runtime) from jobs group by queue")
+            .unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(rows.columns, vec!["queue", "done", "sum(runtime)"]);
+        assert_eq!(
+            rows.rows[0],
+            vec![
+                Value::Text(String::from("mail")),
+                Value::Number(1.0),
+                Value::Number(3.5)
+            ]
+        );
+        assert_eq!(rows.rows.len(), 2);
+    }
+
+    #[test]
+    fn opens_read_only() {
+        let path = create("read-only");
+        for mode in ["read-only", "immutable"] {
+            let mut source = Sqlite.connect(&database(&path, mode)).unwrap();
+            assert!(source.query("select count(*) from jobs").is_ok());
+            assert!(source.query("delete from jobs").is_err());
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn never_creates_files() {
+        let path = env::temp_dir()
+            .join("jikji-missing.db")
+            .display()
+            .to_string();
+        assert!(Sqlite.connect(&database(&path, "read-write")).is_err());
+        assert!(fs::metadata(&path).is_err());
+    }
+}
@@ -0,0 +1,99 @@
+//! HTTP requests for the drivers that query over HTTP.
+
+use super::Error;
+use crate::tls::Tls;
+use base64::prelude::{Engine, BASE64_STANDARD};
+use std::io::Read;
+use std::sync::Arc;
+use std::time::Duration;
+use ureq::{Agent, AgentBuilder, Request, Response};
+
+/// How long a request may take without a `timeout`.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// An agent for HTTP requests, which uses `tls` for HTTPS and gives up on a
+/// request after `timeout`.
+pub fn agent(tls: &Tls, timeout: Option<Duration>) -> Result<Agent, Error> {
+    let connector = tls
+        .connector()
+        .map_err(|err| Error::Settings(format!("TLS: {}", err)))?;
+    Ok(AgentBuilder::new()
+        .tls_connector(Arc::new(connector))
+        .timeout(timeout.unwrap_or(TIMEOUT))
+        .build())
+}
+
+/// The `Authorization` header for basic authentication. The credentials go
+/// in a header rather than the URL, where they would need escaping.
+pub fn basic(username: &str, password: Option<&str>) -> String {
+    let credentials = format!("{}:{}", username, password.unwrap_or_default());
+    format!("Basic {}", BASE64_STANDARD.encode(credentials))
+}
+
+/// Sends `request` with `body`. A response with an error status is an error
+/// that quotes its body, which usually explains what went wrong, and no
+/// response at all means the server is unreachable.
+pub fn send(request: Request, body: &str) -> Result<Response, Error> {
+    match request.send_string(body) {
+        Ok(response) => Ok(response),
+        Err(ureq::Error::Status(status, response)) => {
+            let body = response.into_string().unwrap_or_default();
+            Err(Error::Driver(
+                format!("HTTP {}: {}", status, body.trim()).into(),
+            ))
+        }
+        Err(err) => Err(Error::Unreachable(Box::new(err))),
+    }
+}
+
+/// Reads the whole body of `response`, however long it is.
+pub fn read(response: Response) -> Result<String, Error> {
+    let mut body = String::new();
+    response
+        .into_reader()
+        .read_to_string(&mut body)
+        .map_err(|err| Error::Driver(Box::new(err)))?;
+    Ok(body)
+}
+
+/// Answers one request on a local port with `headers` and `body`, and returns
+/// the port and the request it got.
+#[cfg(test)]
+pub fn serve(headers: &'static str, body: &'static str) -> (u16, std::thread::JoinHandle<String>) {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = std::thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut request = String::new();
+        let mut length = 0;
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            if let Some(value) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                length = value.trim().parse().unwrap();
+            }
+            request.push_str(&line);
+            if line == "\r\n" {
+                break;
+            }
+        }
+        let mut content = vec![0; length];
+        reader.read_exact(&mut content).unwrap();
+        request.push_str(&String::from_utf8(content).unwrap());
+
+        write!(
+            &stream,
+            "HTTP/1.1 200 OK\r\n{}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            headers,
+            body.len(),
+            body
+        )
+        .unwrap();
+        request
+    });
+    (port, server)
+}
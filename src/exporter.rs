@@ -0,0 +1,717 @@
+use crate::config::{Metric, MetricType, MetricValue};
+use crate::driver::{Rows, Value};
+use crate::naming::{self, Naming};
+use prometheus::core::{Collector, Desc};
+use prometheus::proto::{self, LabelPair, MetricFamily};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex, PoisonError};
+
+const DEFAULT_QUANTILES: [f64; 3] = [0.5, 0.9, 0.99];
+
+/// Holds the latest result of one configured metric and exposes it to the
+/// prometheus registry in the metric's declared type. Like the `*Vec` metric
+/// types, it carries one series per distinct set of label values. Clones
+/// share state.
+#[derive(Clone)]
+pub struct Exporter {
+    value: MetricValue,
+    naming: Naming,
+    /// The label columns and, in the same order, their exported names.
+    labels: Vec<String>,
+    label_names: Vec<String>,
+    desc: Desc,
+    families: Arc<Mutex<Vec<MetricFamily>>>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    Prometheus(String),
+    Naming(naming::Error),
+    MissingStates,
+    InvalidQuantile(f64),
+    InvalidBuckets(Vec<f64>),
+    NoColumns,
+    MissingColumn(String),
+    NotANumber(Value),
+    NegativeCounter(f64),
+    DuplicateSeries(Vec<String>),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Prometheus(err) => write!(f, "{}", err),
+            Error::Naming(err) => write!(f, "{}", err),
+            Error::MissingStates => write!(f, "a stateset needs a list of `states`"),
+            Error::InvalidQuantile(q) => write!(f, "quantile {} is not between 0 and 1", q),
+            Error::InvalidBuckets(buckets) => write!(
+                f,
+                "buckets {:?} are not finite and strictly increasing",
+                buckets
+            ),
+            Error::NoColumns => write!(f, "query returned no value column"),
+            Error::MissingColumn(column) => write!(f, "query returned no column {:?}", column),
+            Error::NotANumber(value) => write!(f, "{:?} is not a number", value),
+            Error::NegativeCounter(value) => write!(f, "counter value {} is negative", value),
+            Error::DuplicateSeries(labels) => {
+                write!(f, "more than one row has the label values {:?}", labels)
+            }
+        }
+    }
+}
+
+impl From<naming::Error> for Error {
+    fn from(err: naming::Error) -> Error {
+        Error::Naming(err)
+    }
+}
+
+impl From<prometheus::Error> for Error {
+    fn from(err: prometheus::Error) -> Error {
+        Error::Prometheus(err.to_string())
+    }
+}
+
+/// The rows of a result that share the same label values.
+struct Series<'a> {
+    labels: Vec<String>,
+    values: Vec<&'a Value>,
+}
+
+impl Exporter {
+    /// Creates the exporter for `value`, one of the metrics of `metric`,
+    /// naming it and its labels according to `naming`.
+    pub fn new(metric: &Metric, value: &MetricValue, naming: &Naming) -> Result<Exporter, Error> {
+        match value.kind {
+            MetricType::StateSet if value.states.is_none() => return Err(Error::MissingStates),
+            MetricType::Summary => {
+                let quantiles = value.quantiles.as_deref().unwrap_or(&DEFAULT_QUANTILES);
+                if let Some(q) = quantiles.iter().find(|q| !(0.0..=1.0).contains(*q)) {
+                    return Err(Error::InvalidQuantile(*q));
+                }
+            }
+            MetricType::Histogram => {
+                if let Some(buckets) = &value.buckets {
+                    let increasing = buckets.windows(2).all(|pair| pair[0] < pair[1]);
+                    if !increasing || !buckets.iter().all(|bound| bound.is_finite()) {
+                        return Err(Error::InvalidBuckets(buckets.clone()));
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        let label_names = metric
+            .labels
+            .iter()
+            .map(|label| naming.label_name(label))
+            .collect::<Result<Vec<_>, _>>()?;
+        let help = value.help.clone().unwrap_or_else(|| value.name.clone());
+        let desc = Desc::new(
+            naming.metric_name(&exported_name(value))?,
+            help,
+            label_names.clone(),
+            HashMap::new(),
+        )?;
+
+        Ok(Exporter {
+            value: value.clone(),
+            naming: naming.clone(),
+            labels: metric.labels.clone(),
+            label_names,
+            desc,
+            families: Arc::new(Mutex::new(Vec::new())),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.desc.fq_name
+    }
+
+    /// Replaces the exported samples with ones built from `rows`. On error
+    /// the previous samples are kept.
+    pub fn update(&self, rows: &Rows) -> Result<(), Error> {
+        let metrics = match self.value.kind {
+            MetricType::Info => self.info(rows)?,
+            _ => {
+                let mut metrics = Vec::new();
+                for series in self.group(rows)? {
+                    for mut metric in self.series(&series)? {
+                        let mut labels = self
+                            .label_names
+                            .iter()
+                            .zip(&series.labels)
+                            .map(|(name, value)| label(name, value))
+                            .collect::<Vec<_>>();
+                        labels.extend(metric.take_label());
+                        labels.sort_by(|a, b| a.get_name().cmp(b.get_name()));
+                        metric.set_label(labels.into());
+                        metrics.push(metric);
+                    }
+                }
+                metrics
+            }
+        };
+
+        let mut family = MetricFamily::default();
+        family.set_name(self.desc.fq_name.clone());
+        family.set_help(self.desc.help.clone());
+        family.set_field_type(match self.value.kind {
+            MetricType::Counter => proto::MetricType::COUNTER,
+            MetricType::Histogram => proto::MetricType::HISTOGRAM,
+            MetricType::Summary => proto::MetricType::SUMMARY,
+            MetricType::Gauge | MetricType::Info | MetricType::StateSet => proto::MetricType::GAUGE,
+        });
+        family.set_metric(metrics.into());
+
+        *self.families.lock().unwrap_or_else(PoisonError::into_inner) = vec![family];
+        Ok(())
+    }
+
+    /// Splits `rows` by the values of the label columns, keeping the value
+    /// column of each row. The value column is `column` if configured, and
+    /// otherwise the first column that is not a label.
+    fn group<'a>(&self, rows: &'a Rows) -> Result<Vec<Series<'a>>, Error> {
+        let labels = self
+            .labels
+            .iter()
+            .map(|name| column(rows, name))
+            .collect::<Result<Vec<_>, _>>()?;
+        let value = match &self.value.column {
+            Some(name) => column(rows, name)?,
+            None => (0..rows.columns.len())
+                .find(|index| !labels.contains(index))
+                .ok_or(Error::NoColumns)?,
+        };
+
+        // Without labels there is exactly one series, even for no rows.
+        let mut groups = Vec::new();
+        if labels.is_empty() {
+            groups.push(Series {
+                labels: Vec::new(),
+                values: Vec::new(),
+            });
+        }
+        let mut indices = HashMap::new();
+
+        for row in &rows.rows {
+            let key = labels
+                .iter()
+                .map(|index| text(&row[*index]))
+                .collect::<Vec<_>>();
+            let index = *indices.entry(key.clone()).or_insert_with(|| {
+                if labels.is_empty() {
+                    return 0;
+                }
+                groups.push(Series {
+                    labels: key,
+                    values: Vec::new(),
+                });
+                groups.len() - 1
+            });
+            groups[index].values.push(&row[value]);
+        }
+
+        Ok(groups)
+    }
+
+    fn series(&self, series: &Series) -> Result<Vec<proto::Metric>, Error> {
+        let metrics = match self.value.kind {
+            MetricType::Gauge => single(series)?.map(gauge).into_iter().collect(),
+            MetricType::Counter => match single(series)? {
+                Some(value) if value < 0.0 => return Err(Error::NegativeCounter(value)),
+                value => value.map(counter).into_iter().collect(),
+            },
+            MetricType::Histogram => vec![histogram(&observations(series)?, self.buckets())],
+            MetricType::Summary => vec![summary(&observations(series)?, self.quantiles())],
+            MetricType::StateSet => stateset(
+                series,
+                &self.naming.label_name(&self.desc.fq_name)?,
+                self.states(),
+            ),
+            MetricType::Info => unreachable!("info metrics are not grouped"),
+        };
+        Ok(metrics)
+    }
+
+    /// Exports one sample of `1` per row, labelled with the `labels` columns
+    /// or, if none are configured, with every column.
+    fn info(&self, rows: &Rows) -> Result<Vec<proto::Metric>, Error> {
+        let columns = if self.labels.is_empty() {
+            rows.columns
+                .iter()
+                .enumerate()
+                .map(|(index, name)| Ok((index, self.naming.label_name(name)?)))
+                .collect::<Result<Vec<_>, Error>>()?
+        } else {
+            self.labels
+                .iter()
+                .zip(&self.label_names)
+                .map(|(column_name, name)| Ok((column(rows, column_name)?, name.clone())))
+                .collect::<Result<Vec<_>, Error>>()?
+        };
+
+        let metrics = rows
+            .rows
+            .iter()
+            .map(|row| {
+                let mut metric = gauge(1.0);
+                let mut labels = columns
+                    .iter()
+                    .map(|(index, name)| label(name, &text(&row[*index])))
+                    .collect::<Vec<_>>();
+                labels.sort_by(|a, b| a.get_name().cmp(b.get_name()));
+                metric.set_label(labels.into());
+                metric
+            })
+            .collect();
+
+        Ok(metrics)
+    }
+
+    fn buckets(&self) -> &[f64] {
+        self.value
+            .buckets
+            .as_deref()
+            .unwrap_or(prometheus::DEFAULT_BUCKETS)
+    }
+
+    fn quantiles(&self) -> &[f64] {
+        self.value
+            .quantiles
+            .as_deref()
+            .unwrap_or(&DEFAULT_QUANTILES)
+    }
+
+    fn states(&self) -> &[String] {
+        self.value.states.as_deref().unwrap_or_default()
+    }
+}
+
+impl Collector for Exporter {
+    fn desc(&self) -> Vec<&Desc> {
+        vec![&self.desc]
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        self.families
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+}
+
+/// Info metrics are exposed as gauges, which by convention carry an `_info`
+/// suffix.
+fn exported_name(value: &MetricValue) -> String {
+    if value.kind == MetricType::Info && !value.name.ends_with("_info") {
+        format!("{}_info", value.name)
+    } else {
+        value.name.clone()
+    }
+}
+
+fn column(rows: &Rows, name: &str) -> Result<usize, Error> {
+    rows.columns
+        .iter()
+        .position(|column| column == name)
+        .ok_or_else(|| Error::MissingColumn(name.to_string()))
+}
+
+fn number(value: &Value) -> Result<Option<f64>, Error> {
+    match value {
+        Value::Null => Ok(None),
+        Value::Number(number) => Ok(Some(*number)),
+        Value::Text(text) => text
+            .trim()
+            .parse()
+            .map(Some)
+            .map_err(|_| Error::NotANumber(value.clone())),
+    }
+}
+
+fn text(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::Number(number) => number.to_string(),
+        Value::Text(text) => text.clone(),
+    }
+}
+
+fn label(name: &str, value: &str) -> LabelPair {
+    let mut pair = LabelPair::default();
+    pair.set_name(name.to_string());
+    pair.set_value(value.to_string());
+    pair
+}
+
+/// The value of a series that may hold at most one row. No rows or `NULL`
+/// yield `None`.
+fn single(series: &Series) -> Result<Option<f64>, Error> {
+    match series.values.as_slice() {
+        [] => Ok(None),
+        [value] => number(value),
+        _ => Err(Error::DuplicateSeries(series.labels.clone())),
+    }
+}
+
+/// Every value of a series, skipping `NULL`s.
+fn observations(series: &Series) -> Result<Vec<f64>, Error> {
+    let mut values = Vec::with_capacity(series.values.len());
+    for value in &series.values {
+        if let Some(value) = number(value)? {
+            values.push(value);
+        }
+    }
+    Ok(values)
+}
+
+fn gauge(value: f64) -> proto::Metric {
+    let mut gauge = proto::Gauge::default();
+    gauge.set_value(value);
+    let mut metric = proto::Metric::default();
+    metric.set_gauge(gauge);
+    metric
+}
+
+fn counter(value: f64) -> proto::Metric {
+    let mut counter = proto::Counter::default();
+    counter.set_value(value);
+    let mut metric = proto::Metric::default();
+    metric.set_counter(counter);
+    metric
+}
+
+fn histogram(values: &[f64], bounds: &[f64]) -> proto::Metric {
+    let buckets = bounds
+        .iter()
+        .map(|bound| {
+            let mut bucket = proto::Bucket::default();
+            bucket.set_upper_bound(*bound);
+            bucket.set_cumulative_count(values.iter().filter(|v| *v <= bound).count() as u64);
+            bucket
+        })
+        .collect::<Vec<_>>();
+
+    let mut histogram = proto::Histogram::default();
+    histogram.set_sample_count(values.len() as u64);
+    histogram.set_sample_sum(values.iter().sum());
+    histogram.set_bucket(buckets.into());
+    let mut metric = proto::Metric::default();
+    metric.set_histogram(histogram);
+    metric
+}
+
+/// Computes exact quantiles over the observations using the nearest-rank
+/// method.
+fn summary(values: &[f64], quantiles: &[f64]) -> proto::Metric {
+    let mut sorted = values.to_vec();
+    sorted.sort_by(f64::total_cmp);
+
+    let quantiles = quantiles
+        .iter()
+        .map(|q| {
+            let rank = (q * sorted.len() as f64).ceil() as usize;
+            let mut quantile = proto::Quantile::default();
+            quantile.set_quantile(*q);
+            quantile.set_value(match sorted.len() {
+                0 => f64::NAN,
+                len => sorted[rank.clamp(1, len) - 1],
+            });
+            quantile
+        })
+        .collect::<Vec<_>>();
+
+    let mut summary = proto::Summary::default();
+    summary.set_sample_count(values.len() as u64);
+    summary.set_sample_sum(values.iter().sum());
+    summary.set_quantile(quantiles.into());
+    let mut metric = proto::Metric::default();
+    metric.set_summary(summary);
+    metric
+}
+
+/// Following OpenMetrics, each state is a sample labelled with the metric's
+/// own name. Every value of the series is an active state.
+fn stateset(series: &Series, name: &str, states: &[String]) -> Vec<proto::Metric> {
+    let active = series
+        .values
+        .iter()
+        .map(|value| text(value))
+        .collect::<Vec<_>>();
+
+    states
+        .iter()
+        .map(|state| {
+            let mut metric = gauge(if active.contains(state) { 1.0 } else { 0.0 });
+            metric.set_label(vec![label(name, state)].into());
+            metric
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prometheus::{Encoder, TextEncoder};
+
+    fn metric(kind: &str, extra: &str) -> Metric {
+        toml::from_str(&format!(
+            "name = \"jobs\"\ntype = \"{}\"\nfrequency = \"1m\"\nquery = \"\"\n{}",
+            kind, extra
+        ))
+        .unwrap()
+    }
+
+    fn new(metric: Metric) -> Result<Exporter, Error> {
+        Exporter::new(
+            &metric,
+            &metric.exported_values().unwrap()[0],
+            &Naming::default(),
+        )
+    }
+
+    fn rows(columns: &[&str], rows: Vec<Vec<Value>>) -> Rows {
+        Rows {
+            columns: columns.iter().map(|c| c.to_string()).collect(),
+            rows,
+        }
+    }
+
+    fn numbers(values: &[f64]) -> Rows {
+        rows(
+            &["value"],
+            values.iter().map(|v| vec![Value::Number(*v)]).collect(),
+        )
+    }
+
+    fn render(exporter: &Exporter) -> String {
+        let mut buffer = vec![];
+        TextEncoder::new()
+            .encode(&exporter.collect(), &mut buffer)
+            .unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+
+    #[test]
+    fn exports_gauge() {
+        let exporter = new(metric("gauge", "")).unwrap();
+        exporter.update(&numbers(&[3.0])).unwrap();
+        assert_eq!(
+            render(&exporter),
+            "# HELP jobs jobs\n# TYPE jobs gauge\njobs 3\n"
+        );
+    }
+
+    fn queues() -> Rows {
+        rows(
+            &["queue", "count", "age"],
+            vec![
+                vec![
+                    Value::Text("mail".into()),
+                    Value::Number(3.0),
+                    Value::Number(1.0),
+                ],
+                vec![
+                    Value::Text("sms".into()),
+                    Value::Number(5.0),
+                    Value::Number(2.0),
+                ],
+            ],
+        )
+    }
+
+    #[test]
+    fn exports_one_series_per_row() {
+        let exporter = new(metric("gauge", "labels = [\"queue\"]\nvalue = \"count\"")).unwrap();
+        exporter.update(&queues()).unwrap();
+        assert!(render(&exporter).ends_with("jobs{queue=\"mail\"} 3\njobs{queue=\"sms\"} 5\n"));
+    }
+
+    #[test]
+    fn defaults_value_to_first_unlabelled_column() {
+        let exporter = new(metric("gauge", "labels = [\"queue\"]")).unwrap();
+        exporter.update(&queues()).unwrap();
+        assert!(render(&exporter).ends_with("jobs{queue=\"sms\"} 5\n"));
+    }
+
+    #[test]
+    fn exports_several_values_of_one_query() {
+        let metric: Metric = toml::from_str(
+            "frequency = \"1m\"\nquery = \"\"\nlabels = [\"queue\"]\n\
+             [[values]]\ncolumn = \"count\"\nname = \"jobs\"\n\
+             [[values]]\ncolumn = \"age\"\nname = \"jobs_age\"\ntype = \"counter\"",
+        )
+        .unwrap();
+        let rendered = metric
+            .exported_values()
+            .unwrap()
+            .iter()
+            .map(|value| {
+                let exporter = Exporter::new(&metric, value, &Naming::default()).unwrap();
+                exporter.update(&queues()).unwrap();
+                render(&exporter)
+            })
+            .collect::<Vec<_>>();
+        assert!(rendered[0].ends_with("jobs{queue=\"mail\"} 3\njobs{queue=\"sms\"} 5\n"));
+        assert!(rendered[1].contains("# TYPE jobs_age counter\n"));
+        assert!(rendered[1].ends_with("jobs_age{queue=\"mail\"} 1\njobs_age{queue=\"sms\"} 2\n"));
+    }
+
+    #[test]
+    fn rejects_duplicate_series() {
+        let exporter = new(metric("gauge", "")).unwrap();
+        assert_eq!(
+            exporter.update(&numbers(&[3.0, 7.0])),
+            Err(Error::DuplicateSeries(vec![]))
+        );
+    }
+
+    #[test]
+    fn rejects_missing_column() {
+        let exporter = new(metric("gauge", "labels = [\"queue\"]\nvalue = \"n\"")).unwrap();
+        assert_eq!(
+            exporter.update(&queues()),
+            Err(Error::MissingColumn(String::from("n")))
+        );
+    }
+
+    #[test]
+    fn groups_observations_by_labels() {
+        let exporter = new(metric(
+            "histogram",
+            "labels = [\"queue\"]\nvalue = \"age\"\nbuckets = [1.0]",
+        ))
+        .unwrap();
+        exporter.update(&queues()).unwrap();
+        let text = render(&exporter);
+        assert!(text.contains("jobs_bucket{queue=\"mail\",le=\"1\"} 1\n"));
+        assert!(text.contains("jobs_bucket{queue=\"sms\",le=\"1\"} 0\n"));
+        assert!(text.contains("jobs_count{queue=\"sms\"} 1\n"));
+    }
+
+    #[test]
+    fn exports_counter_that_goes_down_as_reset() {
+        let exporter = new(metric("counter", "")).unwrap();
+        exporter.update(&numbers(&[10.0])).unwrap();
+        exporter.update(&numbers(&[2.0])).unwrap();
+        assert!(render(&exporter).ends_with("# TYPE jobs counter\njobs 2\n"));
+    }
+
+    #[test]
+    fn rejects_negative_counter() {
+        let exporter = new(metric("counter", "")).unwrap();
+        exporter.update(&numbers(&[10.0])).unwrap();
+        assert_eq!(
+            exporter.update(&numbers(&[-1.0])),
+            Err(Error::NegativeCounter(-1.0))
+        );
+        assert!(render(&exporter).ends_with("jobs 10\n"));
+    }
+
+    #[test]
+    fn exports_histogram_of_observations() {
+        let exporter = new(metric("histogram", "buckets = [1.0, 5.0]")).unwrap();
+        exporter.update(&numbers(&[0.5, 2.0, 3.0, 10.0])).unwrap();
+        assert!(render(&exporter).ends_with(
+            "jobs_bucket{le=\"1\"} 1\n\
+             jobs_bucket{le=\"5\"} 3\n\
+             jobs_bucket{le=\"+Inf\"} 4\n\
+             jobs_sum 15.5\n\
+             jobs_count 4\n"
+        ));
+    }
+
+    #[test]
+    fn exports_summary_of_observations() {
+        let exporter = new(metric("summary", "quantiles = [0.5, 1.0]")).unwrap();
+        exporter.update(&numbers(&[4.0, 1.0, 3.0, 2.0])).unwrap();
+        assert!(render(&exporter).ends_with(
+            "jobs{quantile=\"0.5\"} 2\n\
+             jobs{quantile=\"1\"} 4\n\
+             jobs_sum 10\n\
+             jobs_count 4\n"
+        ));
+    }
+
+    #[test]
+    fn rejects_invalid_quantile() {
+        assert!(matches!(
+            new(metric("summary", "quantiles = [1.5]")),
+            Err(Error::InvalidQuantile(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_invalid_buckets() {
+        for buckets in ["[5.0, 1.0]", "[1.0, 1.0]", "[1.0, inf]", "[nan]"] {
+            assert!(matches!(
+                new(metric("histogram", &format!("buckets = {}", buckets))),
+                Err(Error::InvalidBuckets(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn exports_info_labels() {
+        let exporter = new(metric("info", "")).unwrap();
+        exporter
+            .update(&rows(
+                &["version", "edition"],
+                vec![vec![Value::Text("15.2".into()), Value::Text("oss".into())]],
+            ))
+            .unwrap();
+        assert!(render(&exporter)
+            .ends_with("# TYPE jobs_info gauge\njobs_info{edition=\"oss\",version=\"15.2\"} 1\n"));
+    }
+
+    #[test]
+    fn exports_stateset() {
+        let exporter = new(metric("stateset", "states = [\"ok\", \"failed\"]")).unwrap();
+        exporter
+            .update(&rows(&["state"], vec![vec![Value::Text("failed".into())]]))
+            .unwrap();
+        assert!(render(&exporter).ends_with("jobs{jobs=\"ok\"} 0\njobs{jobs=\"failed\"} 1\n"));
+    }
+
+    #[test]
+    fn applies_naming_to_metric_and_label_names() {
+        let metric: Metric = toml::from_str(
+            "name = \"pg.server\"\ntype = \"info\"\nfrequency = \"1m\"\nquery = \"\"",
+        )
+        .unwrap();
+        let naming = Naming {
+            policy: naming::Policy::Sanitize,
+            namespace: Some(String::from("db")),
+        };
+        let exporter =
+            Exporter::new(&metric, &metric.exported_values().unwrap()[0], &naming).unwrap();
+        exporter
+            .update(&rows(
+                &["server version"],
+                vec![vec![Value::Text("15.2".into())]],
+            ))
+            .unwrap();
+        assert!(render(&exporter).ends_with("db_pg_server_info{server_version=\"15.2\"} 1\n"));
+    }
+
+    #[test]
+    fn rejects_invalid_label_column() {
+        let exporter = new(metric("info", "")).unwrap();
+        assert_eq!(
+            exporter.update(&rows(&["server version"], vec![])),
+            Err(Error::Naming(naming::Error::Label(String::from(
+                "server version"
+            ))))
+        );
+    }
+
+    #[test]
+    fn stateset_requires_states() {
+        assert_eq!(
+            new(metric("stateset", "")).err(),
+            Some(Error::MissingStates)
+        );
+    }
+}
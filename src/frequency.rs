@@ -0,0 +1,422 @@
+use chrono::Utc;
+use chrono_tz::Tz;
+use serde::{Deserialize, Deserializer};
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// How often a metric's query runs. Accepts
+///
+/// * durations made of one or more number/unit pairs: `"30s"`, `"15m"`,
+///   `"1h30m"`, `"1d"`, `"500ms"`;
+/// * ISO-8601 durations: `"PT15M"`, `"P1DT12H"`, `"P2W"`;
+/// * cron expressions with five (or six, with seconds) fields and an
+///   optional trailing timezone: `"0 */6 * * * Europe/Berlin"`,
+///   `"@hourly"`. Without a timezone the schedule runs in UTC. With five
+///   fields, days of the week are numbered like in standard cron, from 0 or
+///   7 for Sunday to 6 for Saturday; with six, like in the `cron` crate,
+///   from 1 for Sunday to 7 for Saturday.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Frequency {
+    Every(Duration),
+    Cron(Box<cron::Schedule>, Tz),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Error {
+    Duration(String),
+    Iso8601(String),
+    Cron(String, String),
+    Zero(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Duration(value) => write!(
+                f,
+                "invalid frequency {:?}: expected a duration such as \"15m\" or \"1h30m\" \
+                 (units: ms, s, m, h, d), an ISO-8601 duration such as \"PT15M\", \
+                 or a cron expression",
+                value
+            ),
+            Error::Iso8601(value) => write!(
+                f,
+                "invalid ISO-8601 frequency {:?}: expected P[nW][nD][T[nH][nM][nS]], \
+                 years and months are not supported",
+                value
+            ),
+            Error::Cron(value, reason) => {
+                write!(f, "invalid cron frequency {:?}: {}", value, reason)
+            }
+            Error::Zero(value) => write!(f, "invalid frequency {:?}: must be positive", value),
+        }
+    }
+}
+
+impl Frequency {
+    /// Returns how long to wait before the next run, given that the previous
+    /// run started at `started`.
+    pub fn until_next(&self, started: Instant) -> Duration {
+        match self {
+            Frequency::Every(period) => period.saturating_sub(started.elapsed()),
+            Frequency::Cron(schedule, tz) => schedule
+                .upcoming(*tz)
+                .next()
+                .and_then(|next| (next.with_timezone(&Utc) - Utc::now()).to_std().ok())
+                .unwrap_or(Duration::ZERO),
+        }
+    }
+}
+
+impl TryFrom<String> for Frequency {
+    type Error = Error;
+
+    fn try_from(value: String) -> Result<Frequency, Error> {
+        value.parse()
+    }
+}
+
+impl FromStr for Frequency {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Frequency, Error> {
+        let trimmed = value.trim();
+
+        if trimmed.starts_with('@') || trimmed.contains(char::is_whitespace) {
+            return parse_cron(trimmed);
+        }
+
+        let duration = match trimmed.strip_prefix('P') {
+            Some(iso) => parse_iso8601(iso).ok_or_else(|| Error::Iso8601(value.to_string()))?,
+            None => parse_duration(trimmed).ok_or_else(|| Error::Duration(value.to_string()))?,
+        };
+
+        if duration.is_zero() {
+            return Err(Error::Zero(value.to_string()));
+        }
+
+        Ok(Frequency::Every(duration))
+    }
+}
+
+/// Deserializes an optional duration, written like a frequency that is not a
+/// cron expression, e.g. `"30s"` or `"PT30S"`.
+pub fn duration<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Duration>, D::Error> {
+    match Option::<String>::deserialize(deserializer)? {
+        Some(value) => match value.parse() {
+            Ok(Frequency::Every(duration)) => Ok(Some(duration)),
+            Ok(Frequency::Cron(..)) => Err(serde::de::Error::custom(format!(
+                "invalid duration {:?}: expected a duration such as \"30s\", not a cron expression",
+                value
+            ))),
+            Err(err) => Err(serde::de::Error::custom(err)),
+        },
+        None => Ok(None),
+    }
+}
+
+/// Splits `value` into `(number, unit)` pairs, e.g. `"1h30m"` into
+/// `[(1, "h"), (30, "m")]`.
+fn components(value: &str) -> Option<Vec<(u64, &str)>> {
+    let mut rest = value;
+    let mut components = Vec::new();
+
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let number = rest[..digits].parse().ok()?;
+        rest = &rest[digits..];
+
+        let letters = rest
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(rest.len());
+        if letters == 0 {
+            return None;
+        }
+        components.push((number, &rest[..letters]));
+        rest = &rest[letters..];
+    }
+
+    if components.is_empty() {
+        None
+    } else {
+        Some(components)
+    }
+}
+
+fn seconds(number: u64, scale: u64) -> Option<Duration> {
+    number.checked_mul(scale).map(Duration::from_secs)
+}
+
+fn parse_duration(value: &str) -> Option<Duration> {
+    components(value)?
+        .into_iter()
+        .try_fold(Duration::ZERO, |total, (number, unit)| {
+            let duration = match unit {
+                "ms" => Some(Duration::from_millis(number)),
+                "s" => seconds(number, 1),
+                "m" => seconds(number, 60),
+                "h" => seconds(number, 60 * 60),
+                "d" => seconds(number, 60 * 60 * 24),
+                _ => None,
+            }?;
+            total.checked_add(duration)
+        })
+}
+
+fn parse_iso8601(value: &str) -> Option<Duration> {
+    let (date, time) = match value.split_once('T') {
+        Some((_, "")) => return None,
+        Some((date, time)) => (date, time),
+        None => (value, ""),
+    };
+    let date = if date.is_empty() {
+        Vec::new()
+    } else {
+        components(date)?
+    };
+    let time = if time.is_empty() {
+        Vec::new()
+    } else {
+        components(time)?
+    };
+    if date.is_empty() && time.is_empty() {
+        return None;
+    }
+
+    let date = date.into_iter().map(|(number, unit)| match unit {
+        "W" => seconds(number, 60 * 60 * 24 * 7),
+        "D" => seconds(number, 60 * 60 * 24),
+        _ => None,
+    });
+    let time = time.into_iter().map(|(number, unit)| match unit {
+        "H" => seconds(number, 60 * 60),
+        "M" => seconds(number, 60),
+        "S" => seconds(number, 1),
+        _ => None,
+    });
+
+    date.chain(time)
+        .try_fold(Duration::ZERO, |total, duration| {
+            total.checked_add(duration?)
+        })
+}
+
+fn parse_cron(value: &str) -> Result<Frequency, Error> {
+    let error = |reason: String| Error::Cron(value.to_string(), reason);
+
+    let mut fields: Vec<&str> = value.split_whitespace().collect();
+    let tz = match fields.last().map(|field| field.parse::<Tz>()) {
+        Some(Ok(tz)) => {
+            fields.pop();
+            tz
+        }
+        _ => Tz::UTC,
+    };
+
+    let expression = match fields.len() {
+        // Standard cron has no seconds field; run at the top of the minute.
+        5 => {
+            let days = days_of_week(fields[4]).map_err(error)?;
+            format!("0 {} {}", fields[..4].join(" "), days)
+        }
+        1 if fields[0].starts_with('@') => fields[0].to_string(),
+        6 => fields.join(" "),
+        n => {
+            return Err(error(format!(
+                "expected 5 or 6 fields and an optional timezone, found {} fields",
+                n
+            )))
+        }
+    };
+
+    let schedule = cron::Schedule::from_str(&expression).map_err(|err| error(err.to_string()))?;
+    if schedule.upcoming(tz).next().is_none() {
+        return Err(error(String::from("the schedule never fires")));
+    }
+
+    Ok(Frequency::Cron(Box::new(schedule), tz))
+}
+
+/// Renumbers a standard cron day-of-week field, where Sunday is 0 or 7, for
+/// the `cron` crate, where it is 1. Numeric values, ranges and steps become
+/// a list of days; `*` and names mean the same in both and are kept.
+fn days_of_week(field: &str) -> Result<String, String> {
+    let invalid = |item: &str| format!("invalid day of the week {:?}", item);
+    let mut items = Vec::new();
+    for item in field.split(',') {
+        if item.starts_with('*') || item.contains(|c: char| c.is_ascii_alphabetic()) {
+            items.push(item.to_string());
+            continue;
+        }
+
+        let (range, step) = match item.split_once('/') {
+            Some((range, step)) => (range, Some(step.parse::<u8>().map_err(|_| invalid(item))?)),
+            None => (item, None),
+        };
+        let day = |day: &str| day.parse::<u8>().ok().filter(|day| *day <= 7);
+        let (first, last) = match range.split_once('-') {
+            Some((first, last)) => (day(first), day(last)),
+            // `1/2` runs from Monday on, `1` on Monday only.
+            None if step.is_some() => (day(range), Some(6)),
+            None => (day(range), day(range)),
+        };
+        let (first, last) = match (first, last) {
+            (Some(first), Some(last)) if first <= last => (first, last),
+            _ => return Err(invalid(item)),
+        };
+        let step = match step {
+            Some(0) => return Err(invalid(item)),
+            Some(step) => step,
+            None => 1,
+        };
+
+        let mut days: Vec<u8> = (first..=last)
+            .step_by(usize::from(step))
+            .map(|day| day % 7 + 1)
+            .collect();
+        days.sort_unstable();
+        days.dedup();
+        items.extend(days.iter().map(u8::to_string));
+    }
+    Ok(items.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Datelike, Weekday};
+
+    fn parse(value: &str) -> Result<Duration, Error> {
+        match value.parse::<Frequency>()? {
+            Frequency::Every(duration) => Ok(duration),
+            Frequency::Cron(..) => panic!("{} parsed as cron", value),
+        }
+    }
+
+    #[test]
+    fn parses_units() {
+        assert_eq!(parse("250ms"), Ok(Duration::from_millis(250)));
+        assert_eq!(parse("30s"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse("15m"), Ok(Duration::from_secs(15 * 60)));
+        assert_eq!(parse("2h"), Ok(Duration::from_secs(2 * 60 * 60)));
+        assert_eq!(parse("1d"), Ok(Duration::from_secs(24 * 60 * 60)));
+    }
+
+    #[test]
+    fn parses_compound_durations() {
+        assert_eq!(parse("1h30m"), Ok(Duration::from_secs(90 * 60)));
+        assert_eq!(parse("1m30s500ms"), Ok(Duration::from_millis(90_500)));
+    }
+
+    #[test]
+    fn parses_iso8601_durations() {
+        assert_eq!(parse("PT15M"), Ok(Duration::from_secs(15 * 60)));
+        assert_eq!(parse("PT1H30M"), Ok(Duration::from_secs(90 * 60)));
+        assert_eq!(parse("P1DT12H"), Ok(Duration::from_secs(36 * 60 * 60)));
+        assert_eq!(parse("P2W"), Ok(Duration::from_secs(14 * 24 * 60 * 60)));
+    }
+
+    #[test]
+    fn rejects_invalid_iso8601_durations() {
+        assert!(matches!(parse("P1M"), Err(Error::Iso8601(_))));
+        assert!(matches!(parse("PT"), Err(Error::Iso8601(_))));
+        assert!(matches!(parse("P"), Err(Error::Iso8601(_))));
+        assert!(matches!(parse("PT15X"), Err(Error::Iso8601(_))));
+    }
+
+    #[test]
+    fn rejects_missing_or_unknown_units() {
+        assert!(parse("15").is_err());
+        assert!(parse("m").is_err());
+        assert!(parse("15x").is_err());
+        assert!(parse("").is_err());
+    }
+
+    #[test]
+    fn rejects_zero() {
+        assert_eq!(parse("0s"), Err(Error::Zero(String::from("0s"))));
+    }
+
+    #[test]
+    fn parses_cron_with_timezone() {
+        match "0 */6 * * * Europe/Berlin".parse::<Frequency>() {
+            Ok(Frequency::Cron(schedule, tz)) => {
+                assert_eq!(schedule.source(), "0 0 */6 * * *");
+                assert_eq!(tz, chrono_tz::Europe::Berlin);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parses_cron_without_timezone() {
+        assert!(matches!(
+            "30 2 * * *".parse::<Frequency>(),
+            Ok(Frequency::Cron(_, Tz::UTC))
+        ));
+        assert!(matches!(
+            "@hourly".parse::<Frequency>(),
+            Ok(Frequency::Cron(..))
+        ));
+    }
+
+    #[test]
+    fn numbers_days_of_the_week_like_standard_cron() {
+        let source = |value: &str| match value.parse::<Frequency>() {
+            Ok(Frequency::Cron(schedule, _)) => schedule.source().to_string(),
+            other => panic!("unexpected {:?}", other),
+        };
+        // Monday to Friday, and Sunday.
+        assert_eq!(source("0 3 * * 1-5"), "0 0 3 * * 2,3,4,5,6");
+        assert_eq!(source("0 3 * * 0"), "0 0 3 * * 1");
+        assert_eq!(source("0 3 * * 7"), "0 0 3 * * 1");
+        assert_eq!(source("0 3 * * 5-7,mon"), "0 0 3 * * 1,6,7,mon");
+        assert_eq!(source("0 3 * * 0-6/2"), "0 0 3 * * 1,3,5,7");
+        assert_eq!(source("0 3 * * */2"), "0 0 3 * * */2");
+        assert_eq!(source("0 3 * * MON-FRI"), "0 0 3 * * MON-FRI");
+
+        let Ok(Frequency::Cron(schedule, _)) = "0 3 * * 1-5".parse::<Frequency>() else {
+            panic!("not a cron frequency");
+        };
+        assert!(schedule
+            .upcoming(Tz::UTC)
+            .take(10)
+            .all(|time| !matches!(time.weekday(), Weekday::Sat | Weekday::Sun)));
+
+        assert!(matches!(
+            "0 3 * * 8".parse::<Frequency>(),
+            Err(Error::Cron(..))
+        ));
+        assert!(matches!(
+            "0 3 * * 5-1".parse::<Frequency>(),
+            Err(Error::Cron(..))
+        ));
+    }
+
+    #[test]
+    fn rejects_invalid_cron() {
+        assert!(matches!(
+            "0 */6 * * Europe/Berlin".parse::<Frequency>(),
+            Err(Error::Cron(..))
+        ));
+        assert!(matches!(
+            "0 99 * * *".parse::<Frequency>(),
+            Err(Error::Cron(..))
+        ));
+        assert!(matches!(
+            "0 0 * * * Mars/Olympus".parse::<Frequency>(),
+            Err(Error::Cron(..))
+        ));
+    }
+
+    #[test]
+    fn waits_for_the_remainder_of_an_interval() {
+        let frequency = Frequency::Every(Duration::from_secs(60));
+        let wait = frequency.until_next(Instant::now());
+        assert!(wait <= Duration::from_secs(60) && wait > Duration::from_secs(59));
+    }
+}
@@ -0,0 +1,94 @@
+use serde::{Deserialize, Deserializer};
+use std::env;
+
+/// Replaces every `${NAME}` in `text` with the value of the environment
+/// variable `NAME`. `$${` stands for a literal `${`.
+pub fn interpolate(text: &str) -> Result<String, String> {
+    interpolate_with(text, |name| env::var(name).ok())
+}
+
+fn interpolate_with(text: &str, lookup: impl Fn(&str) -> Option<String>) -> Result<String, String> {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find('$') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        if let Some(escaped) = rest.strip_prefix("$${") {
+            result.push_str("${");
+            rest = escaped;
+        } else if let Some(variable) = rest.strip_prefix("${") {
+            let end = variable
+                .find('}')
+                .ok_or_else(|| String::from("unterminated `${`"))?;
+            let name = &variable[..end];
+            if name.is_empty() {
+                return Err(String::from("empty `${}`"));
+            }
+            let value =
+                lookup(name).ok_or_else(|| format!("environment variable {} is not set", name))?;
+            result.push_str(&value);
+            rest = &variable[end + 1..];
+        } else {
+            result.push('$');
+            rest = &rest[1..];
+        }
+    }
+
+    result.push_str(rest);
+    Ok(result)
+}
+
+/// Deserializes an optional string, interpolating environment variables.
+pub fn option<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    match Option::<String>::deserialize(deserializer)? {
+        Some(text) => interpolate(&text)
+            .map(Some)
+            .map_err(serde::de::Error::custom),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lookup(name: &str) -> Option<String> {
+        match name {
+            "USER" => Some(String::from("jikji")),
+            "EMPTY" => Some(String::new()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn replaces_variables() {
+        assert_eq!(
+            interpolate_with("${USER}@${USER}-db${EMPTY}", lookup),
+            Ok(String::from("jikji@jikji-db"))
+        );
+        assert_eq!(
+            interpolate_with("plain $ text", lookup),
+            Ok(String::from("plain $ text"))
+        );
+    }
+
+    #[test]
+    fn keeps_escaped_dollars() {
+        assert_eq!(
+            interpolate_with("$${USER} is ${USER}", lookup),
+            Ok(String::from("${USER} is jikji"))
+        );
+    }
+
+    #[test]
+    fn rejects_unset_and_malformed_variables() {
+        assert_eq!(
+            interpolate_with("${PASSWORD}", lookup),
+            Err(String::from("environment variable PASSWORD is not set"))
+        );
+        assert!(interpolate_with("${USER", lookup).is_err());
+        assert!(interpolate_with("${}", lookup).is_err());
+    }
+}
@@ -1,36 +1,38 @@
 use hyper::{
-    header::CONTENT_TYPE,
+    header::{AUTHORIZATION, CONTENT_TYPE, WWW_AUTHENTICATE},
     service::{make_service_fn, service_fn},
-    Body, Request, Response, Server,
+    Body, Method, Request, Response, Server, StatusCode,
 };
-use lazy_static::lazy_static;
-use prometheus::{labels, opts, register_counter, register_gauge, register_histogram_vec};
-use prometheus::{Counter, Encoder, Gauge, HistogramVec, TextEncoder};
-use serde::Deserialize;
+use log::{error, info};
+use prometheus::{Encoder, Registry, TextEncoder};
+use std::env;
 use std::fs;
-use std::iter::Map;
-use toml::Table;
-
-lazy_static! {
-    static ref HTTP_COUNTER: Counter = register_counter!(opts!(
-        "example_http_requests_total",
-        "Number of HTTP requests made.",
-        labels! {"handler" => "all",}
-    ))
-    .unwrap();
-    static ref HTTP_BODY_GAUGE: Gauge = register_gauge!(opts!(
-        "example_http_response_size_bytes",
-        "The HTTP response sizes in bytes.",
-        labels! {"handler" => "all",}
-    ))
-    .unwrap();
-    static ref HTTP_REQ_HISTOGRAM: HistogramVec = register_histogram_vec!(
-        "example_http_request_duration_seconds",
-        "The HTTP request latencies in seconds.",
-        &["handler"]
-    )
-    .unwrap();
-}
+use std::io::{self, Write};
+use std::process;
+use std::sync::Arc;
+
+mod breaker;
+mod cli;
+mod config;
+mod driver;
+mod dsn;
+mod exporter;
+mod frequency;
+mod interpolate;
+mod logger;
+mod naming;
+#[cfg(feature = "postgres")]
+mod pgpass;
+mod pool;
+mod reload;
+mod scheduler;
+mod telemetry;
+mod tls;
+
+use cli::{Args, Command, Invocation};
+use reload::Reloader;
+use scheduler::Scheduler;
+use telemetry::{HTTP_BODY_GAUGE, HTTP_COUNTER, HTTP_REQ_HISTOGRAM};
 
 async fn serve_req(_req: Request<Body>) -> Result<Response<Body>, hyper::Error> {
     let encoder = TextEncoder::new();
@@ -54,109 +56,162 @@ async fn serve_req(_req: Request<Body>) -> Result<Response<Body>, hyper::Error>
     Ok(response)
 }
 
-#[derive(Deserialize)]
-struct Config {
-    title: String,
-    databases: Vec<Database>
-}
-
-#[derive(Deserialize)]
-struct Database {
-    driver: String,
-    hostname: String,
-    port: u16,
-    username: String,
-    password: String,
-    database: String,
-    metrics: Vec<Metric>
-}
+/// Handles `POST /-/reload`, which requires the configured bearer token.
+async fn reload_req(
+    req: &Request<Body>,
+    reloader: &Arc<Reloader>,
+    token: Option<&str>,
+) -> (StatusCode, String) {
+    let token = match token {
+        Some(token) => token,
+        None => {
+            return (
+                StatusCode::FORBIDDEN,
+                String::from("reloading is disabled, see --web.reload-token-file\n"),
+            )
+        }
+    };
+    if req.method() != Method::POST {
+        return (StatusCode::METHOD_NOT_ALLOWED, String::from("use POST\n"));
+    }
+    let header = req
+        .headers()
+        .get(AUTHORIZATION)
+        .map(|value| value.as_bytes());
+    if !reload::authorized(header, token) {
+        return (StatusCode::UNAUTHORIZED, String::from("unauthorized\n"));
+    }
 
-#[derive(Deserialize)]
-struct Metric {
-    name: String,
-    frequency: String,
+    match reloader.reload().await {
+        Ok(changes) => (
+            StatusCode::OK,
+            format!(
+                "reloaded: {} added, {} removed, {} unchanged\n",
+                changes.added, changes.removed, changes.unchanged
+            ),
+        ),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, format!("{}\n", err)),
+    }
 }
 
-fn parse_config() ->  Config {
-    let config = fs::read_to_string("example.toml").expect("Config not found");
-    toml::from_str(&config).unwrap()
-}
+async fn route(
+    req: Request<Body>,
+    reloader: Arc<Reloader>,
+    token: Option<Arc<str>>,
+) -> Result<Response<Body>, hyper::Error> {
+    if req.uri().path() != "/-/reload" {
+        return serve_req(req).await;
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    const test_config: &str = r###"
-title = "Default Jikji Config"
-
-[[databases]]
-driver = "postgres"
-hostname = "127.0.0.1"
-port = 5432
-username = "postgres"
-password= "secret"
-database= "postgres"
-
-[[databases.metrics]]
-name="hubspot.actions.delayed"
-type="counter"
-frequency="15m"
-query = """ \
-    select count(*) from actions_scheduled
-    where completed is null
-    and scheduled < now() - interval '15 minutes'
-    and scheduled > now() - interval '1 day';
-    """
-"###;
-
-    fn config() ->  Config {
-        toml::from_str(test_config).unwrap()
+    let (status, body) = reload_req(&req, &reloader, token.as_deref()).await;
+    let mut response = Response::builder().status(status);
+    if status == StatusCode::UNAUTHORIZED {
+        response = response.header(WWW_AUTHENTICATE, "Bearer");
     }
+    Ok(response.body(Body::from(body)).unwrap())
+}
 
-    #[test]
-    fn it_works() {
-        let result = 2 + 2;
-        assert_eq!(result, 4);
+async fn serve(config: config::Config, args: &Args) {
+    let token: Option<Arc<str>> = match &args.reload_token_file {
+        Some(path) => match fs::read_to_string(path) {
+            Ok(token) if !token.trim().is_empty() => Some(token.trim().into()),
+            Ok(_) => {
+                error!("{}: the reload token is empty", path);
+                process::exit(1);
+            }
+            Err(err) => {
+                error!("{}: {}", path, err);
+                process::exit(1);
+            }
+        },
+        None => None,
+    };
+
+    telemetry::register();
+    let scheduler = Scheduler::start(config, prometheus::default_registry().clone());
+    let reloader = Reloader::new(&args.config, scheduler);
+    reload::on_sighup(reloader.clone());
+    if args.watch_config {
+        if let Err(err) = reload::watch(reloader.clone()) {
+            error!("cannot watch {}: {}", args.config, err);
+        }
     }
 
-    #[test]
-    fn parses_name() {
-        assert_eq!(
-            config().title,
-            String::from("Default Jikji Config")
-        );
+    let addr = args.listen_address;
+    let serve_future = match Server::try_bind(&addr) {
+        Ok(builder) => builder.serve(make_service_fn(move |_| {
+            let reloader = reloader.clone();
+            let token = token.clone();
+            async move {
+                Ok::<_, hyper::Error>(service_fn(move |req| {
+                    route(req, reloader.clone(), token.clone())
+                }))
+            }
+        })),
+        Err(err) => {
+            error!("cannot listen on {}: {}", addr, err);
+            process::exit(1);
+        }
+    };
+    info!("Listening on http://{}", addr);
 
+    if let Err(err) = serve_future.await {
+        error!("server error: {}", err);
     }
+}
 
-    #[test]
-    fn parses_database_driver() {
-        assert_eq!(
-            config().databases.get(0).unwrap().driver,
-            String::from("postgres")
-        );
+/// Runs every query one time into a fresh registry and prints the result.
+async fn once(config: config::Config) {
+    let registry = Registry::new();
+    for job in scheduler::jobs(config, &registry) {
+        job.collect().await;
     }
 
-    #[test]
-    fn parses_metric_name() {
-        assert_eq!(
-            config().databases.get(0).unwrap().metrics.get(0).unwrap().name,
-            String::from("hubspot.actions.delayed")
-        );
-    }
+    let mut buffer = vec![];
+    TextEncoder::new()
+        .encode(&registry.gather(), &mut buffer)
+        .unwrap();
+    io::stdout().write_all(&buffer).unwrap();
 }
 
-
 #[tokio::main]
 async fn main() {
-    let config = parse_config();
-    let addr = ([127, 0, 0, 1], 9898).into();
-    println!("Listening on http://{}", addr);
-
-    let serve_future = Server::bind(&addr).serve(make_service_fn(|_| async {
-        Ok::<_, hyper::Error>(service_fn(serve_req))
-    }));
+    let args = env::args().collect::<Vec<_>>();
+    let program = args.first().map(String::as_str).unwrap_or("jikji");
+
+    let args = match cli::parse(program, &args[1.min(args.len())..]) {
+        Ok(Invocation::Run(args)) => args,
+        Ok(Invocation::Help(usage)) => {
+            print!("{}", usage);
+            return;
+        }
+        Ok(Invocation::Version) => {
+            println!("jikji {}", env!("CARGO_PKG_VERSION"));
+            return;
+        }
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, cli::usage(program));
+            process::exit(2);
+        }
+    };
+    logger::init(args.log_level);
+
+    let config = match config::parse_config(&args.config) {
+        Ok(config) => config,
+        Err(err) => {
+            error!("{}", err);
+            process::exit(1);
+        }
+    };
+    if let Err(err) = scheduler::check(&config) {
+        error!("{}: {}", args.config, err);
+        process::exit(1);
+    }
+    info!("Loaded {} from {}", config.title, args.config);
 
-    if let Err(err) = serve_future.await {
-        eprintln!("server error: {}", err);
+    match args.command {
+        Command::Check => println!("{}: OK", args.config),
+        Command::Once => once(config).await,
+        Command::Serve => serve(config, &args).await,
     }
 }
@@ -0,0 +1,183 @@
+use serde::Deserialize;
+use std::fmt;
+
+/// How metric and label names that are not valid prometheus names are
+/// handled, and an optional namespace prepended to every metric name.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Naming {
+    #[serde(default)]
+    pub policy: Policy,
+    pub namespace: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Policy {
+    /// Invalid names are a configuration error.
+    #[default]
+    Reject,
+    /// Invalid characters, such as the dots in `hubspot.actions.delayed`,
+    /// are replaced with underscores.
+    Sanitize,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Error {
+    Metric(String),
+    Label(String),
+    ReservedLabel(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Metric(name) => write!(
+                f,
+                "{:?} is not a valid metric name: it must match [a-zA-Z_:][a-zA-Z0-9_:]* \
+                 (set `policy = \"sanitize\"` under [naming] to replace invalid characters)",
+                name
+            ),
+            Error::Label(name) => write!(
+                f,
+                "{:?} is not a valid label name: it must match [a-zA-Z_][a-zA-Z0-9_]* \
+                 (set `policy = \"sanitize\"` under [naming] to replace invalid characters)",
+                name
+            ),
+            Error::ReservedLabel(name) => write!(
+                f,
+                "{:?} is not a valid label name: names starting with \"__\" are reserved",
+                name
+            ),
+        }
+    }
+}
+
+impl Naming {
+    /// Returns the exported name for the configured metric name `name`.
+    pub fn metric_name(&self, name: &str) -> Result<String, Error> {
+        let name = match &self.namespace {
+            Some(namespace) => format!("{}_{}", namespace, name),
+            None => name.to_string(),
+        };
+
+        match self.policy {
+            _ if is_valid_metric_name(&name) => Ok(name),
+            Policy::Sanitize if !name.is_empty() => Ok(sanitize(&name, true)),
+            _ => Err(Error::Metric(name)),
+        }
+    }
+
+    /// Returns the exported label name for the column `name`.
+    pub fn label_name(&self, name: &str) -> Result<String, Error> {
+        let label = match self.policy {
+            _ if is_valid_label_name(name) => name.to_string(),
+            Policy::Sanitize if !name.is_empty() => sanitize(name, false),
+            _ => return Err(Error::Label(name.to_string())),
+        };
+
+        if label.starts_with("__") {
+            return Err(Error::ReservedLabel(name.to_string()));
+        }
+        Ok(label)
+    }
+}
+
+fn is_name_char(c: char, colon: bool) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || (colon && c == ':')
+}
+
+fn is_valid(name: &str, colon: bool) -> bool {
+    match name.chars().next() {
+        Some(first) if first.is_ascii_digit() => false,
+        Some(_) => name.chars().all(|c| is_name_char(c, colon)),
+        None => false,
+    }
+}
+
+pub fn is_valid_metric_name(name: &str) -> bool {
+    is_valid(name, true)
+}
+
+pub fn is_valid_label_name(name: &str) -> bool {
+    is_valid(name, false)
+}
+
+/// Replaces every invalid character with an underscore and prefixes names
+/// that start with a digit with one.
+fn sanitize(name: &str, colon: bool) -> String {
+    let mut sanitized = String::with_capacity(name.len() + 1);
+    if name.starts_with(|c: char| c.is_ascii_digit()) {
+        sanitized.push('_');
+    }
+    sanitized.extend(
+        name.chars()
+            .map(|c| if is_name_char(c, colon) { c } else { '_' }),
+    );
+    sanitized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sanitizing(namespace: Option<&str>) -> Naming {
+        Naming {
+            policy: Policy::Sanitize,
+            namespace: namespace.map(String::from),
+        }
+    }
+
+    #[test]
+    fn validates_names() {
+        assert!(is_valid_metric_name("hubspot_actions:delayed"));
+        assert!(!is_valid_metric_name("hubspot.actions.delayed"));
+        assert!(!is_valid_metric_name("1st"));
+        assert!(!is_valid_metric_name(""));
+        assert!(is_valid_label_name("queue_1"));
+        assert!(!is_valid_label_name("queue:name"));
+    }
+
+    #[test]
+    fn rejects_invalid_names_by_default() {
+        let naming = Naming::default();
+        assert_eq!(
+            naming.metric_name("hubspot.actions.delayed"),
+            Err(Error::Metric(String::from("hubspot.actions.delayed")))
+        );
+        assert_eq!(
+            naming.label_name("queue name"),
+            Err(Error::Label(String::from("queue name")))
+        );
+    }
+
+    #[test]
+    fn sanitizes_names() {
+        let naming = sanitizing(None);
+        assert_eq!(
+            naming.metric_name("hubspot.actions.delayed"),
+            Ok(String::from("hubspot_actions_delayed"))
+        );
+        assert_eq!(naming.metric_name("5xx"), Ok(String::from("_5xx")));
+        assert_eq!(
+            naming.label_name("queue:name"),
+            Ok(String::from("queue_name"))
+        );
+    }
+
+    #[test]
+    fn prefixes_namespace() {
+        assert_eq!(
+            sanitizing(Some("hubspot")).metric_name("actions.delayed"),
+            Ok(String::from("hubspot_actions_delayed"))
+        );
+    }
+
+    #[test]
+    fn rejects_reserved_labels() {
+        assert_eq!(
+            sanitizing(None).label_name("__name__"),
+            Err(Error::ReservedLabel(String::from("__name__")))
+        );
+    }
+}
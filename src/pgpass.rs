@@ -0,0 +1,136 @@
+use log::warn;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+/// One `hostname:port:database:username:password` line of a password file.
+/// Any of the first four fields may be `*`, which matches everything.
+#[derive(Debug, PartialEq, Eq)]
+struct Entry {
+    hostname: String,
+    port: String,
+    database: String,
+    username: String,
+    password: String,
+}
+
+/// The password for connecting as `username` to `database` on
+/// `hostname:port`, looked up like libpq does in `$PGPASSFILE` or
+/// `~/.pgpass`.
+pub fn lookup(hostname: &str, port: u16, database: &str, username: &str) -> Option<String> {
+    let path = path()?;
+    let text = fs::read_to_string(&path).ok()?;
+    if !private(&path) {
+        warn!(
+            "{}: ignoring password file, it must not be accessible by group or others",
+            path.display()
+        );
+        return None;
+    }
+
+    let port = port.to_string();
+    parse(&text)
+        .into_iter()
+        .find(|entry| {
+            matches(&entry.hostname, hostname)
+                && matches(&entry.port, &port)
+                && matches(&entry.database, database)
+                && matches(&entry.username, username)
+        })
+        .map(|entry| entry.password)
+}
+
+fn path() -> Option<PathBuf> {
+    match env::var_os("PGPASSFILE") {
+        Some(path) => Some(PathBuf::from(path)),
+        None => env::var_os("HOME").map(|home| PathBuf::from(home).join(".pgpass")),
+    }
+}
+
+#[cfg(unix)]
+fn private(path: &PathBuf) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    fs::metadata(path)
+        .map(|metadata| metadata.permissions().mode() & 0o077 == 0)
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn private(_path: &PathBuf) -> bool {
+    true
+}
+
+fn matches(pattern: &str, value: &str) -> bool {
+    pattern == "*" || pattern == value
+}
+
+/// Parses a password file, skipping comments and malformed lines. `\:` and
+/// `\\` stand for a literal colon and backslash.
+fn parse(text: &str) -> Vec<Entry> {
+    text.lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| {
+            let mut fields = split(line).into_iter();
+            Some(Entry {
+                hostname: fields.next()?,
+                port: fields.next()?,
+                database: fields.next()?,
+                username: fields.next()?,
+                password: fields.next()?,
+            })
+        })
+        .collect()
+}
+
+/// Splits `line` on its first four unescaped colons.
+fn split(line: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => fields.last_mut().unwrap().extend(chars.next()),
+            ':' if fields.len() < 5 => fields.push(String::new()),
+            c => fields.last_mut().unwrap().push(c),
+        }
+    }
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(fields: [&str; 5]) -> Entry {
+        Entry {
+            hostname: fields[0].to_string(),
+            port: fields[1].to_string(),
+            database: fields[2].to_string(),
+            username: fields[3].to_string(),
+            password: fields[4].to_string(),
+        }
+    }
+
+    #[test]
+    fn parses_entries() {
+        assert_eq!(
+            parse("# comment\ndb.example.com:5432:*:jikji:s3cret\nincomplete:line\n"),
+            vec![entry(["db.example.com", "5432", "*", "jikji", "s3cret"])]
+        );
+    }
+
+    #[test]
+    fn unescapes_colons_and_backslashes() {
+        assert_eq!(
+            parse(r"*:*:*:jikji:pa\:ss\\word:with:colons"),
+            vec![entry(["*", "*", "*", "jikji", r"pa:ss\word:with:colons"])]
+        );
+    }
+
+    #[test]
+    fn matches_wildcards() {
+        assert!(matches("*", "anything"));
+        assert!(matches("db", "db"));
+        assert!(!matches("db", "db2"));
+    }
+}
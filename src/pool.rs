@@ -0,0 +1,304 @@
+//! Connections kept open between queries, in a pool per database. The pool
+//! also caps how many queries run against its database at the same time:
+//! each needs a permit, of which there are `max_connections`. Connecting is
+//! backed off from after it fails, see `breaker`.
+
+use crate::breaker::Breaker;
+use crate::config::Database;
+use crate::driver::{self, Error, Source};
+use log::warn;
+use std::mem;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, Weak};
+use std::time::{Duration, Instant};
+use tokio::runtime::Handle;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// How many connections a database gets without `max_connections`.
+pub const MAX_CONNECTIONS: usize = 2;
+/// How long a connection may be idle without `idle_timeout`.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+/// How often idle connections are closed or opened.
+const MAINTENANCE_INTERVAL: Duration = Duration::from_secs(30);
+
+pub struct Pool {
+    database: Arc<Database>,
+    max_connections: usize,
+    min_idle: usize,
+    idle_timeout: Duration,
+    /// Whether the driver's connections may be used again.
+    reusable: bool,
+    /// Whether connecting shows the database is up, see
+    /// `Driver::reaches_on_connect`.
+    reaches_on_connect: bool,
+    permits: Arc<Semaphore>,
+    breaker: Breaker,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    /// Idle connections and since when, the longest idle first.
+    idle: Vec<(Box<dyn Source>, Instant)>,
+    /// How many connections are open or being opened, idle or not.
+    open: usize,
+}
+
+impl Pool {
+    /// A pool for `database`, which closes and opens idle connections in the
+    /// background as long as it exists.
+    pub fn new(database: Arc<Database>) -> Arc<Pool> {
+        let max_connections = database.max_connections.unwrap_or(MAX_CONNECTIONS);
+        let pool = Arc::new(Pool {
+            max_connections,
+            min_idle: database.min_idle.unwrap_or_default(),
+            idle_timeout: database.idle_timeout.unwrap_or(IDLE_TIMEOUT),
+            reusable: driver::get(&database.driver).is_none_or(|driver| driver.reusable()),
+            reaches_on_connect: driver::get(&database.driver)
+                .is_none_or(|driver| driver.reaches_on_connect()),
+            permits: Arc::new(Semaphore::new(max_connections)),
+            breaker: Breaker::new(&database),
+            state: Mutex::default(),
+            database,
+        });
+        if let Ok(runtime) = Handle::try_current() {
+            runtime.spawn(maintain(Arc::downgrade(&pool)));
+        }
+        pool
+    }
+
+    /// Waits until the database runs fewer than `max_connections` queries.
+    pub async fn permit(&self) -> OwnedSemaphorePermit {
+        self.permits
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("the pool never closes its semaphore")
+    }
+
+    /// Calls `f` with an idle connection, or a new one if there is none. The
+    /// connection goes back to the pool unless `f` fails, since it may have
+    /// failed because the connection broke.
+    pub fn with_connection<T>(
+        &self,
+        _permit: OwnedSemaphorePermit,
+        f: impl FnOnce(&mut dyn Source) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let idle = self.lock().idle.pop();
+        let mut source = match idle {
+            Some((source, _)) => source,
+            None => self.open()?,
+        };
+
+        let result = f(source.as_mut());
+        match &result {
+            Err(Error::Unreachable(_)) => {
+                self.record(false);
+                // Otherwise the next queries would go out on idle connections
+                // rather than wait for the breaker.
+                self.close_idle();
+            }
+            Ok(_) if !self.reaches_on_connect => self.record(true),
+            _ => {}
+        }
+        match (&result, self.reusable) {
+            (Ok(_), true) => self.lock().idle.push((source, Instant::now())),
+            _ => self.close(vec![source]),
+        }
+        result
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn open(&self) -> Result<Box<dyn Source>, Error> {
+        self.breaker.admit()?;
+        self.lock().open += 1;
+        let source = driver::connect(&self.database).inspect_err(|_| self.lock().open -= 1);
+        if source.is_err() || self.reaches_on_connect {
+            self.record(source.is_ok());
+        }
+        source
+    }
+
+    /// Counts an attempt to reach the database in its breaker.
+    fn record(&self, reached: bool) {
+        if self.breaker.record(reached) {
+            // Connections opened before are likely as broken as new ones.
+            self.close_idle();
+        }
+    }
+
+    fn close_idle(&self) {
+        let idle = mem::take(&mut self.lock().idle);
+        self.close(idle.into_iter().map(|(source, _)| source).collect());
+    }
+
+    fn close(&self, sources: Vec<Box<dyn Source>>) {
+        self.lock().open -= sources.len();
+        drop(sources);
+    }
+
+    /// Closes the connections idle for longer than the idle timeout, except
+    /// for `min_idle` of them, and opens connections until `min_idle` are.
+    fn maintain(&self) {
+        let expired = {
+            let mut state = self.lock();
+            let expired = state
+                .idle
+                .iter()
+                .take_while(|(_, since)| since.elapsed() >= self.idle_timeout)
+                .count()
+                .min(state.idle.len().saturating_sub(self.min_idle));
+            state
+                .idle
+                .drain(..expired)
+                .map(|(source, _)| source)
+                .collect()
+        };
+        self.close(expired);
+
+        // Opening a connection takes a permit like a query does, so that no
+        // more than `max_connections` are ever open.
+        while let (true, Ok(_permit)) = (self.reusable, self.permits.clone().try_acquire_owned()) {
+            {
+                let state = self.lock();
+                if state.idle.len() >= self.min_idle || state.open >= self.max_connections {
+                    return;
+                }
+            }
+            match self.open() {
+                Ok(source) => self.lock().idle.push((source, Instant::now())),
+                Err(Error::BackingOff { .. }) => return,
+                Err(err) => {
+                    warn!("cannot connect to {}: {}", self.database.describe(), err);
+                    return;
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Pool {
+    fn drop(&mut self) {
+        // Closing some connections blocks, which the async executor must not.
+        let idle = mem::take(&mut self.lock().idle);
+        if let Ok(runtime) = Handle::try_current() {
+            runtime.spawn_blocking(move || drop(idle));
+        }
+    }
+}
+
+/// Maintains `pool` every `MAINTENANCE_INTERVAL` until it is dropped.
+async fn maintain(pool: Weak<Pool>) {
+    loop {
+        tokio::time::sleep(MAINTENANCE_INTERVAL).await;
+        let Some(pool) = pool.upgrade() else {
+            return;
+        };
+        let _ = tokio::task::spawn_blocking(move || pool.maintain()).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pool(settings: &str) -> Arc<Pool> {
+        Pool::new(Arc::new(
+            toml::from_str(&format!("driver = \"fake\"\nmetrics = []\n{}", settings)).unwrap(),
+        ))
+    }
+
+    fn counts(pool: &Pool) -> (usize, usize) {
+        let state = pool.lock();
+        (state.idle.len(), state.open)
+    }
+
+    #[tokio::test]
+    async fn reuses_connections_until_a_query_fails() {
+        let pool = pool("");
+        for _ in 0..3 {
+            let permit = pool.permit().await;
+            assert!(pool
+                .with_connection(permit, |source| source.query("one\n1"))
+                .is_ok());
+        }
+        assert_eq!(counts(&pool), (1, 1));
+
+        let permit = pool.permit().await;
+        assert!(pool
+            .with_connection(permit, |source| source.query(""))
+            .is_err());
+        assert_eq!(counts(&pool), (0, 0));
+    }
+
+    #[tokio::test]
+    async fn caps_concurrent_queries() {
+        let pool = pool("max_connections = 2");
+        let first = pool.permit().await;
+        let _second = pool.permit().await;
+        assert!(pool.permits.clone().try_acquire_owned().is_err());
+
+        drop(first);
+        assert!(pool.permits.clone().try_acquire_owned().is_ok());
+    }
+
+    #[tokio::test]
+    async fn keeps_min_idle_connections_open() {
+        let pool = pool("max_connections = 3\nmin_idle = 2\nidle_timeout = \"50ms\"");
+        pool.maintain();
+        assert_eq!(counts(&pool), (2, 2));
+
+        // Two queries at once need a third connection, which expires later.
+        let (first, second) = (pool.permit().await, pool.permit().await);
+        pool.with_connection(first, |_| {
+            pool.with_connection(second, |source| source.query("one\n1"))
+        })
+        .unwrap();
+        assert_eq!(counts(&pool), (2, 2));
+        let permit = pool.permit().await;
+        pool.with_connection(permit, |_| {
+            pool.maintain();
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(counts(&pool), (3, 3));
+
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        pool.maintain();
+        assert_eq!(counts(&pool), (2, 2));
+    }
+
+    #[tokio::test]
+    async fn backs_off_from_connecting_after_a_failure() {
+        let pool = pool("hostname = \"db.invalid\"");
+        let permit = pool.permit().await;
+        assert!(matches!(
+            pool.with_connection(permit, |source| source.query("one\n1")),
+            Err(Error::Driver(_))
+        ));
+        let permit = pool.permit().await;
+        assert!(matches!(
+            pool.with_connection(permit, |source| source.query("one\n1")),
+            Err(Error::BackingOff { failures: 1, .. })
+        ));
+        assert_eq!(counts(&pool), (0, 0));
+    }
+
+    #[tokio::test]
+    async fn backs_off_after_a_query_cannot_reach_the_database() {
+        let pool = pool("port = 1");
+        let permit = pool.permit().await;
+        assert!(matches!(
+            pool.with_connection(permit, |source| source.query("one\n1")),
+            Err(Error::Unreachable(_))
+        ));
+        let permit = pool.permit().await;
+        assert!(matches!(
+            pool.with_connection(permit, |source| source.query("one\n1")),
+            Err(Error::BackingOff { failures: 1, .. })
+        ));
+        assert_eq!(counts(&pool), (0, 0));
+    }
+}
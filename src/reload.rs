@@ -0,0 +1,187 @@
+use crate::config;
+use crate::scheduler::{Changes, Scheduler};
+use chrono::Utc;
+use lazy_static::lazy_static;
+use log::{error, info, warn};
+use notify::{Event, RecursiveMode, Watcher};
+use prometheus::core::Collector;
+use prometheus::Gauge;
+use std::path::Path;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// How long to wait for further changes after the configuration file
+/// changed, as saving a file usually causes several events.
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+lazy_static! {
+    static ref LAST_RELOAD_SUCCESSFUL: Gauge = Gauge::new(
+        "jikji_config_last_reload_successful",
+        "Whether the last configuration reload attempt was successful."
+    )
+    .unwrap();
+    static ref LAST_RELOAD_SUCCESS: Gauge = Gauge::new(
+        "jikji_config_last_reload_success_timestamp_seconds",
+        "Timestamp of the last successful configuration reload."
+    )
+    .unwrap();
+}
+
+/// The gauges about reloading, for `telemetry` to register.
+pub fn collectors() -> Vec<Box<dyn Collector>> {
+    vec![
+        Box::new(LAST_RELOAD_SUCCESSFUL.clone()),
+        Box::new(LAST_RELOAD_SUCCESS.clone()),
+    ]
+}
+
+/// Reads the configuration file again and applies it to the running
+/// scheduler.
+pub struct Reloader {
+    path: String,
+    scheduler: Mutex<Scheduler>,
+}
+
+impl Reloader {
+    pub fn new(path: &str, scheduler: Scheduler) -> Arc<Reloader> {
+        succeeded();
+        Arc::new(Reloader {
+            path: path.to_string(),
+            scheduler: Mutex::new(scheduler),
+        })
+    }
+
+    /// Reloads the configuration. If it is invalid, the error is logged and
+    /// returned and the running configuration is kept.
+    pub async fn reload(self: &Arc<Self>) -> Result<Changes, String> {
+        // Reading the file blocks, which the async executor must not.
+        let reloader = self.clone();
+        tokio::task::spawn_blocking(move || reloader.reload_blocking())
+            .await
+            .unwrap_or_else(|err| {
+                error!("Reloading failed: {}", err);
+                LAST_RELOAD_SUCCESSFUL.set(0.0);
+                Err(format!("reloading failed: {}", err))
+            })
+    }
+
+    fn reload_blocking(&self) -> Result<Changes, String> {
+        let result = config::parse_config(&self.path)
+            .map_err(|err| err.to_string())
+            .and_then(|config| {
+                self.scheduler
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .reload(config)
+            });
+
+        match &result {
+            Ok(changes) => {
+                info!(
+                    "Reloaded {}: {} added, {} removed, {} unchanged",
+                    self.path, changes.added, changes.removed, changes.unchanged
+                );
+                succeeded();
+            }
+            Err(err) => {
+                error!("Keeping the running configuration: {}", err);
+                LAST_RELOAD_SUCCESSFUL.set(0.0);
+            }
+        }
+        result
+    }
+}
+
+fn succeeded() {
+    LAST_RELOAD_SUCCESSFUL.set(1.0);
+    LAST_RELOAD_SUCCESS.set(Utc::now().timestamp() as f64);
+}
+
+/// Reloads on every SIGHUP.
+#[cfg(unix)]
+pub fn on_sighup(reloader: Arc<Reloader>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(err) => {
+            warn!("cannot listen for SIGHUP: {}", err);
+            return;
+        }
+    };
+    tokio::spawn(async move {
+        while hangups.recv().await.is_some() {
+            info!("Received SIGHUP");
+            let _ = reloader.reload().await;
+        }
+    });
+}
+
+#[cfg(not(unix))]
+pub fn on_sighup(_reloader: Arc<Reloader>) {}
+
+/// Reloads whenever the configuration file changes. Its directory is watched
+/// rather than the file itself, so editors that replace the file on save
+/// trigger a reload too.
+pub fn watch(reloader: Arc<Reloader>) -> notify::Result<()> {
+    let path = Path::new(&reloader.path);
+    let file = path.file_name().map(|file| file.to_os_string());
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+
+    let (changed, mut changes) = mpsc::unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+        if let Ok(event) = event {
+            let ours = event
+                .paths
+                .iter()
+                .any(|path| path.file_name() == file.as_deref());
+            if ours && !event.kind.is_access() {
+                let _ = changed.send(());
+            }
+        }
+    })?;
+    watcher.watch(directory, RecursiveMode::NonRecursive)?;
+
+    tokio::spawn(async move {
+        let _watcher = watcher;
+        while changes.recv().await.is_some() {
+            tokio::time::sleep(DEBOUNCE).await;
+            while changes.try_recv().is_ok() {}
+            let _ = reloader.reload().await;
+        }
+    });
+    Ok(())
+}
+
+/// Whether the `Authorization` header `header` carries the bearer `token`.
+/// The comparison takes the same time wherever the first difference is.
+pub fn authorized(header: Option<&[u8]>, token: &str) -> bool {
+    let given = match header.and_then(|header| header.strip_prefix(b"Bearer ")) {
+        Some(given) => given,
+        None => return false,
+    };
+    given.len() == token.len()
+        && given
+            .iter()
+            .zip(token.as_bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks_bearer_token() {
+        assert!(authorized(Some(b"Bearer s3cret"), "s3cret"));
+        assert!(!authorized(Some(b"Bearer s3creT"), "s3cret"));
+        assert!(!authorized(Some(b"Bearer s3cret2"), "s3cret"));
+        assert!(!authorized(Some(b"Basic s3cret"), "s3cret"));
+        assert!(!authorized(None, "s3cret"));
+    }
+}
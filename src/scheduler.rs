@@ -0,0 +1,379 @@
+use crate::config::{Config, Database, Metric};
+use crate::driver::Error;
+use crate::exporter::Exporter;
+use crate::naming::Naming;
+use crate::pool::Pool;
+use crate::telemetry;
+use log::{debug, error, warn};
+use prometheus::Registry;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::OwnedSemaphorePermit;
+use tokio::task::JoinHandle;
+
+/// One configured query and the exporters fed by its result.
+#[derive(Clone)]
+pub struct Job {
+    database: Arc<Database>,
+    index: usize,
+    exporters: Arc<Vec<Exporter>>,
+    /// The connections to the database, shared by all of its jobs.
+    pool: Arc<Pool>,
+}
+
+/// Creates a job per configured metric and registers its exporters in
+/// `registry`. Metrics that cannot be registered are logged and skipped.
+pub fn jobs(config: Config, registry: &Registry) -> Vec<Job> {
+    let naming = config.naming;
+    let mut jobs = Vec::new();
+
+    for database in config.databases {
+        let database = Arc::new(database);
+        let pool = Pool::new(database.clone());
+
+        for index in 0..database.metrics.len() {
+            let metric = &database.metrics[index];
+            match register(metric, &naming, registry) {
+                Ok(exporters) => jobs.push(Job {
+                    database: database.clone(),
+                    index,
+                    exporters: Arc::new(exporters),
+                    pool: pool.clone(),
+                }),
+                Err(err) => error!("metric {}: {}", metric.describe(), err),
+            }
+        }
+    }
+
+    jobs
+}
+
+/// The jobs of the running configuration and their background tasks.
+pub struct Scheduler {
+    registry: Registry,
+    naming: Naming,
+    running: Vec<(Job, JoinHandle<()>)>,
+}
+
+/// What applying a new configuration changed.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Changes {
+    pub added: usize,
+    pub removed: usize,
+    pub unchanged: usize,
+}
+
+impl Scheduler {
+    /// Registers and starts the jobs of `config` in `registry`.
+    pub fn start(config: Config, registry: Registry) -> Scheduler {
+        let mut scheduler = Scheduler {
+            registry,
+            naming: config.naming.clone(),
+            running: Vec::new(),
+        };
+        scheduler.apply(config);
+        scheduler
+    }
+
+    /// Switches to `config`, restarting only the jobs whose query or
+    /// connection changed and unregistering the metrics of removed ones. If
+    /// any metric of `config` cannot be registered, nothing changes.
+    pub fn reload(&mut self, config: Config) -> Result<Changes, String> {
+        check(&config)?;
+        if config.naming != self.naming {
+            self.naming = config.naming.clone();
+            self.stop(|_| true);
+        }
+        Ok(self.apply(config))
+    }
+
+    fn apply(&mut self, config: Config) -> Changes {
+        // Jobs keep the pool of the running configuration unless the way
+        // they connect changed.
+        let mut wanted = Vec::new();
+        for database in config.databases {
+            let database = Arc::new(database);
+            let pool = match self
+                .running
+                .iter()
+                .find(|(job, _)| job.database.same_connection(&database))
+            {
+                Some((job, _)) => job.pool.clone(),
+                None => Pool::new(database.clone()),
+            };
+            for index in 0..database.metrics.len() {
+                wanted.push((database.clone(), index, pool.clone()));
+            }
+        }
+
+        let mut changes = Changes {
+            removed: self.stop(|job| {
+                !wanted
+                    .iter()
+                    .any(|(database, index, _)| job.runs(database, &database.metrics[*index]))
+            }),
+            ..Changes::default()
+        };
+
+        for (database, index, pool) in wanted {
+            let metric = &database.metrics[index];
+            if self
+                .running
+                .iter()
+                .any(|(job, _)| job.runs(&database, metric))
+            {
+                changes.unchanged += 1;
+                continue;
+            }
+            match register(metric, &self.naming, &self.registry) {
+                Ok(exporters) => {
+                    let job = Job {
+                        database: database.clone(),
+                        index,
+                        exporters: Arc::new(exporters),
+                        pool,
+                    };
+                    self.running.push((job.clone(), tokio::spawn(run(job))));
+                    changes.added += 1;
+                }
+                Err(err) => error!("metric {}: {}", metric.describe(), err),
+            }
+        }
+        changes
+    }
+
+    /// Stops the jobs matching `filter` and unregisters their metrics.
+    fn stop(&mut self, filter: impl Fn(&Job) -> bool) -> usize {
+        let (stopped, running) = self.running.drain(..).partition(|(job, _)| filter(job));
+        self.running = running;
+
+        let stopped: Vec<(Job, JoinHandle<()>)> = stopped;
+        for (job, handle) in &stopped {
+            handle.abort();
+            for exporter in job.exporters.iter() {
+                let _ = self.registry.unregister(Box::new(exporter.clone()));
+            }
+        }
+        stopped.len()
+    }
+}
+
+/// Registers every metric of `config` in a scratch registry with the
+/// exporter's own metrics, which fails where starting it would skip a
+/// metric, e.g. for a stateset without `states`, two metrics of the same
+/// name or one named like an exporter metric.
+pub fn check(config: &Config) -> Result<(), String> {
+    let scratch = telemetry::registry();
+    for database in &config.databases {
+        for metric in &database.metrics {
+            register(metric, &config.naming, &scratch)
+                .map_err(|err| format!("metric {}: {}", metric.describe(), err))?;
+        }
+    }
+    Ok(())
+}
+
+/// Registers one exporter per value of `metric`.
+fn register(
+    metric: &Metric,
+    naming: &Naming,
+    registry: &Registry,
+) -> Result<Vec<Exporter>, String> {
+    let mut exporters = Vec::new();
+    for value in metric.exported_values()? {
+        let exporter = Exporter::new(metric, &value, naming).map_err(|err| err.to_string())?;
+        exporters.push(exporter);
+    }
+
+    for (index, exporter) in exporters.iter().enumerate() {
+        if let Err(err) = registry.register(Box::new(exporter.clone())) {
+            for registered in &exporters[..index] {
+                let _ = registry.unregister(Box::new(registered.clone()));
+            }
+            return Err(format!("{}: {}", exporter.name(), err));
+        }
+    }
+    Ok(exporters)
+}
+
+/// Runs `job`'s query once at startup and then on the metric's own
+/// frequency, storing the result in its exporters so scrapes only ever read
+/// the latest cached value.
+async fn run(job: Job) {
+    loop {
+        let started = Instant::now();
+        job.collect().await;
+        tokio::time::sleep(job.metric().frequency.until_next(started)).await;
+    }
+}
+
+impl Job {
+    fn metric(&self) -> &Metric {
+        &self.database.metrics[self.index]
+    }
+
+    /// Whether this job runs `metric` against `database`'s connection.
+    fn runs(&self, database: &Database, metric: &Metric) -> bool {
+        self.metric() == metric && self.database.same_connection(database)
+    }
+
+    /// Runs the query once and hands its rows to every exporter, so each
+    /// value is updated or fails independently. The query waits while the
+    /// database already runs `max_connections` others.
+    pub async fn collect(&self) {
+        let permit = self.pool.permit().await;
+        // The postgres client blocks, so queries run off the async executor.
+        let job = self.clone();
+        if let Err(err) = tokio::task::spawn_blocking(move || job.collect_blocking(permit)).await {
+            error!("collection task failed: {}", err);
+        }
+    }
+
+    fn collect_blocking(&self, permit: OwnedSemaphorePermit) {
+        let database = &self.database;
+        let metric = self.metric();
+        let rows = self
+            .pool
+            .with_connection(permit, |source| source.collect(metric));
+
+        let rows = match rows {
+            Ok(rows) => rows,
+            // Why connecting failed was logged when it did.
+            Err(err @ Error::BackingOff { .. }) => {
+                debug!(
+                    "metric {} on {}: {}",
+                    metric.describe(),
+                    database.describe(),
+                    err
+                );
+                return;
+            }
+            Err(err) => {
+                warn!(
+                    "metric {} on {}: {}",
+                    metric.describe(),
+                    database.describe(),
+                    err
+                );
+                return;
+            }
+        };
+
+        for exporter in self.exporters.iter() {
+            if let Err(err) = exporter.update(&rows) {
+                warn!(
+                    "metric {} on {}: {}",
+                    exporter.name(),
+                    database.describe(),
+                    err
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(metrics: &[(&str, &str)]) -> Config {
+        let mut text = String::from("title = \"test\"\n\n[[databases]]\ndriver = \"fake\"\n");
+        for (name, query) in metrics {
+            text.push_str(&format!(
+                "\n[[databases.metrics]]\nname = \"{}\"\nfrequency = \"1h\"\nquery = \"{}\"\n",
+                name, query
+            ));
+        }
+        toml::from_str(&text).unwrap()
+    }
+
+    fn registered(registry: &Registry, name: &str) -> bool {
+        let metric = &config(&[(name, "select 0")]).databases[0].metrics[0];
+        register(metric, &Naming::default(), registry).is_err()
+    }
+
+    #[tokio::test]
+    async fn reload_restarts_only_changed_jobs() {
+        let registry = Registry::new();
+        let mut scheduler = Scheduler::start(
+            config(&[("one", "select 1"), ("two", "select 2")]),
+            registry.clone(),
+        );
+
+        let changes = scheduler
+            .reload(config(&[
+                ("one", "select 1"),
+                ("two", "select 22"),
+                ("three", "select 3"),
+            ]))
+            .unwrap();
+        assert_eq!(
+            changes,
+            Changes {
+                added: 2,
+                removed: 1,
+                unchanged: 1
+            }
+        );
+
+        let changes = scheduler.reload(config(&[("three", "select 3")])).unwrap();
+        assert_eq!(changes.removed, 2);
+        assert!(!registered(&registry, "one"));
+        assert!(registered(&registry, "three"));
+    }
+
+    #[test]
+    fn check_rejects_metrics_that_cannot_be_registered() {
+        assert_eq!(check(&config(&[("one", "select 1")])), Ok(()));
+        assert!(check(&config(&[("two", "select 2"), ("two", "select 3")])).is_err());
+        assert!(check(&config(&[("jikji_database_breaker_open", "select 1")])).is_err());
+        if cfg!(target_os = "linux") {
+            assert!(check(&config(&[("process_open_fds", "select 1")])).is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn reload_keeps_running_config_on_conflicts() {
+        let registry = Registry::new();
+        let mut scheduler = Scheduler::start(config(&[("one", "select 1")]), registry.clone());
+
+        assert!(scheduler
+            .reload(config(&[("two", "select 2"), ("two", "select 3")]))
+            .is_err());
+        assert_eq!(scheduler.running.len(), 1);
+        assert!(registered(&registry, "one"));
+        assert!(!registered(&registry, "two"));
+    }
+
+    #[tokio::test]
+    async fn collects_rows_from_the_driver() {
+        let config: Config = toml::from_str(
+            r#"
+title = "test"
+
+[[databases]]
+driver = "fake"
+
+[[databases.metrics]]
+name = "jobs"
+frequency = "1h"
+labels = ["queue"]
+query = """
+queue,count
+mail,3
+"""
+"#,
+        )
+        .unwrap();
+        let registry = Registry::new();
+        for job in jobs(config, &registry) {
+            job.collect().await;
+        }
+
+        let families = registry.gather();
+        assert_eq!(families[0].get_name(), "jobs");
+        let metric = &families[0].get_metric()[0];
+        assert_eq!(metric.get_label()[0].get_value(), "mail");
+        assert_eq!(metric.get_gauge().get_value(), 3.0);
+    }
+}
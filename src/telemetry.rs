@@ -0,0 +1,69 @@
+//! The exporter's own metrics, about itself rather than its databases. They
+//! are registered before the configuration's metrics, so that a metric of
+//! the configuration cannot take their names.
+
+use crate::{breaker, reload};
+use lazy_static::lazy_static;
+use prometheus::core::Collector;
+use prometheus::{histogram_opts, labels, opts};
+use prometheus::{Counter, Gauge, HistogramVec, Registry};
+
+lazy_static! {
+    pub static ref HTTP_COUNTER: Counter = Counter::with_opts(opts!(
+        "example_http_requests_total",
+        "Number of HTTP requests made.",
+        labels! {"handler" => "all",}
+    ))
+    .unwrap();
+    pub static ref HTTP_BODY_GAUGE: Gauge = Gauge::with_opts(opts!(
+        "example_http_response_size_bytes",
+        "The HTTP response sizes in bytes.",
+        labels! {"handler" => "all",}
+    ))
+    .unwrap();
+    pub static ref HTTP_REQ_HISTOGRAM: HistogramVec = HistogramVec::new(
+        histogram_opts!(
+            "example_http_request_duration_seconds",
+            "The HTTP request latencies in seconds."
+        ),
+        &["handler"]
+    )
+    .unwrap();
+}
+
+fn collectors() -> Vec<Box<dyn Collector>> {
+    let mut collectors: Vec<Box<dyn Collector>> = vec![
+        Box::new(HTTP_COUNTER.clone()),
+        Box::new(HTTP_BODY_GAUGE.clone()),
+        Box::new(HTTP_REQ_HISTOGRAM.clone()),
+    ];
+    collectors.extend(reload::collectors());
+    collectors.extend(breaker::collectors());
+    collectors
+}
+
+/// Registers the exporter's own metrics in the default registry, which has
+/// the process metrics already.
+pub fn register() {
+    for collector in collectors() {
+        prometheus::register(collector).expect("the exporter's metrics are registered first");
+    }
+}
+
+/// A registry with the same metrics as the default one after `register`,
+/// to try registering the configuration's metrics in.
+pub fn registry() -> Registry {
+    let registry = Registry::new();
+    #[cfg(target_os = "linux")]
+    registry
+        .register(Box::new(
+            prometheus::process_collector::ProcessCollector::for_self(),
+        ))
+        .expect("the registry is empty");
+    for collector in collectors() {
+        registry
+            .register(collector)
+            .expect("the exporter's metrics have distinct names");
+    }
+    registry
+}
@@ -0,0 +1,146 @@
+#[cfg(native_tls)]
+use native_tls::{Certificate, Identity, TlsConnector};
+use serde::Deserialize;
+#[cfg(native_tls)]
+use std::fs;
+use std::str::FromStr;
+
+/// The names of the TLS settings, which are the same as libpq's.
+#[cfg(network)]
+pub const PARAMETERS: [&str; 4] = ["sslmode", "sslrootcert", "sslcert", "sslkey"];
+
+/// Whether and how a connection uses TLS, like libpq's `sslmode`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SslMode {
+    Disable,
+    /// Use TLS if the server supports it, without verifying its certificate.
+    #[default]
+    Prefer,
+    /// Always use TLS. As with libpq, the server certificate is only
+    /// verified if `sslrootcert` is set.
+    Require,
+    /// Always use TLS and verify that a trusted CA signed the server
+    /// certificate.
+    VerifyCa,
+    /// Like `verify-ca`, and also check that the certificate is for the
+    /// host connected to.
+    VerifyFull,
+}
+
+impl FromStr for SslMode {
+    type Err = String;
+
+    fn from_str(mode: &str) -> Result<SslMode, String> {
+        match mode {
+            "disable" => Ok(SslMode::Disable),
+            "prefer" => Ok(SslMode::Prefer),
+            "require" => Ok(SslMode::Require),
+            "verify-ca" => Ok(SslMode::VerifyCa),
+            "verify-full" => Ok(SslMode::VerifyFull),
+            other => Err(format!("invalid sslmode {:?}", other)),
+        }
+    }
+}
+
+/// The TLS settings of a connection. Without `rootcert`, servers are
+/// verified against the system's trusted CAs.
+#[cfg(network)]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Tls {
+    pub mode: SslMode,
+    pub rootcert: Option<String>,
+    pub cert: Option<String>,
+    pub key: Option<String>,
+}
+
+#[cfg(network)]
+impl Tls {
+    /// Sets the setting `name`, one of `PARAMETERS`, from its libpq value.
+    pub fn set(&mut self, name: &str, value: String) -> Result<(), String> {
+        match name {
+            "sslmode" => self.mode = value.parse()?,
+            "sslrootcert" => self.rootcert = Some(value),
+            "sslcert" => self.cert = Some(value),
+            "sslkey" => self.key = Some(value),
+            other => return Err(format!("unknown TLS setting {:?}", other)),
+        }
+        Ok(())
+    }
+
+    /// Whether the server certificate must be signed by a trusted CA.
+    pub fn verifies_certificate(&self) -> bool {
+        match self.mode {
+            SslMode::VerifyCa | SslMode::VerifyFull => true,
+            SslMode::Require => self.rootcert.is_some(),
+            SslMode::Disable | SslMode::Prefer => false,
+        }
+    }
+
+    /// Whether the server certificate must be for the host connected to.
+    #[cfg(uri)]
+    pub fn verifies_hostname(&self) -> bool {
+        self.mode == SslMode::VerifyFull
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        match (&self.cert, &self.key) {
+            (Some(_), None) | (None, Some(_)) => {
+                Err(String::from("`sslcert` and `sslkey` must be set together"))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Builds a TLS connector, reading the certificate and key files anew so
+    /// that rotated certificates are picked up.
+    #[cfg(native_tls)]
+    pub fn connector(&self) -> Result<TlsConnector, String> {
+        let mut builder = TlsConnector::builder();
+
+        if let Some(path) = &self.rootcert {
+            let certificates = Certificate::stack_from_pem(&read(path)?)
+                .map_err(|err| format!("{}: {}", path, err))?;
+            for certificate in certificates {
+                builder.add_root_certificate(certificate);
+            }
+            builder.disable_built_in_roots(true);
+        }
+        if let (Some(cert), Some(key)) = (&self.cert, &self.key) {
+            let identity = Identity::from_pkcs8(&read(cert)?, &read(key)?)
+                .map_err(|err| format!("{}, {}: {}", cert, key, err))?;
+            builder.identity(identity);
+        }
+
+        builder.danger_accept_invalid_certs(!self.verifies_certificate());
+        builder.danger_accept_invalid_hostnames(!self.verifies_hostname());
+        builder.build().map_err(|err| err.to_string())
+    }
+}
+
+#[cfg(native_tls)]
+fn read(path: &str) -> Result<Vec<u8>, String> {
+    fs::read(path).map_err(|err| format!("{}: {}", path, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_libpq_modes() {
+        assert_eq!("verify-full".parse(), Ok(SslMode::VerifyFull));
+        assert_eq!("disable".parse(), Ok(SslMode::Disable));
+        assert!("allow".parse::<SslMode>().is_err());
+    }
+
+    #[cfg(network)]
+    #[test]
+    fn requires_cert_and_key_together() {
+        let mut tls = Tls::default();
+        tls.set("sslcert", String::from("client.crt")).unwrap();
+        assert!(tls.validate().is_err());
+        tls.set("sslkey", String::from("client.key")).unwrap();
+        assert_eq!(tls.validate(), Ok(()));
+    }
+}